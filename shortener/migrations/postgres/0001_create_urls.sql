@@ -0,0 +1,6 @@
+-- IF NOT EXISTS everywhere: deployments from before migrations already have
+-- parts of this schema
+CREATE TABLE IF NOT EXISTS urls (
+    id TEXT PRIMARY KEY,
+    url TEXT NOT NULL UNIQUE
+);
@@ -0,0 +1,4 @@
+ALTER TABLE urls
+    ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS max_clicks BIGINT,
+    ADD COLUMN IF NOT EXISTS clicks BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS clicks (
+    seq BIGSERIAL PRIMARY KEY,
+    id TEXT NOT NULL,
+    clicked_at TIMESTAMPTZ NOT NULL,
+    referrer TEXT,
+    user_agent TEXT,
+    ip_hash TEXT
+);
+
+CREATE INDEX IF NOT EXISTS clicks_id_idx ON clicks (id);
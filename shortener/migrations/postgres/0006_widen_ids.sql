@@ -0,0 +1,4 @@
+-- deployments from before migrations created `id CHAR(6)`, which 0001 left
+-- alone: longer ids were rejected and short ones came back blank padded
+ALTER TABLE urls ALTER COLUMN id TYPE TEXT;
+UPDATE urls SET id = rtrim(id) WHERE id <> rtrim(id);
@@ -0,0 +1,5 @@
+-- values the server generates once and has to keep across restarts
+CREATE TABLE IF NOT EXISTS settings (
+    name TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
@@ -0,0 +1,47 @@
+use crate::error::Error;
+
+const MIN_LEN: usize = 3;
+const MAX_LEN: usize = 32;
+
+/// Slugs that collide with routes or are kept for internal use
+const RESERVED: &[&str] = &[
+    "test", "api", "admin", "static", "bulk", "export", "healthz", "readyz", "metrics",
+];
+
+/// Check that `alias` can be used as a custom id: 3 to 32 ascii
+/// alphanumerics, `-` or `_`, and not a reserved word
+pub fn validate(alias: &str) -> Result<(), Error> {
+    if !(MIN_LEN..=MAX_LEN).contains(&alias.len()) {
+        return Err(Error::InvalidAlias(format!(
+            "length must be between {MIN_LEN} and {MAX_LEN}"
+        )));
+    }
+    if !alias
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(Error::InvalidAlias(
+            "only ascii letters, digits, '-' and '_' are allowed".to_string(),
+        ));
+    }
+    if RESERVED.iter().any(|r| r.eq_ignore_ascii_case(alias)) {
+        return Err(Error::InvalidAlias(format!("{alias} is reserved")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_should_work() {
+        assert!(validate("spring-sale_2024").is_ok());
+        assert!(validate("ab").is_err());
+        assert!(validate(&"a".repeat(33)).is_err());
+        assert!(validate("summer sale").is_err());
+        assert!(validate("caf\u{e9}").is_err());
+        assert!(validate("test").is_err());
+        assert!(validate("TEST").is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::error::Error;
+use crate::store::UrlStore;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// A single redirect hit
+#[derive(Debug, Clone)]
+pub struct Click {
+    pub id: String,
+    pub clicked_at: DateTime<Utc>,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_hash: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkStats {
+    pub total: i64,
+    pub daily: Vec<DailyClicks>,
+    pub top_referrers: Vec<ReferrerClicks>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct DailyClicks {
+    /// `YYYY-MM-DD` in UTC
+    pub day: String,
+    pub clicks: i64,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct ReferrerClicks {
+    pub referrer: String,
+    pub clicks: i64,
+}
+
+/// How many referrers `LinkStats` reports
+pub const TOP_REFERRERS: i64 = 10;
+
+/// ClickRecorder queues clicks and writes them to the store in batches
+/// from a background task, so recording never blocks a redirect
+#[derive(Debug, Clone)]
+pub struct ClickRecorder {
+    tx: mpsc::Sender<Click>,
+    salt: Arc<str>,
+}
+
+impl ClickRecorder {
+    pub fn spawn(store: Arc<dyn UrlStore>, salt: &str) -> Self {
+        Self::spawn_with(store, salt, 1024, 256, Duration::from_secs(1))
+    }
+
+    /// Spawn the writer task, a batch is flushed once it has `batch_size`
+    /// clicks or `flush_every` elapsed, whichever comes first
+    pub fn spawn_with(
+        store: Arc<dyn UrlStore>,
+        salt: &str,
+        capacity: usize,
+        batch_size: usize,
+        flush_every: Duration,
+    ) -> Self {
+        let (tx, mut rx) = mpsc::channel(capacity);
+
+        tokio::spawn(async move {
+            let mut batch: Vec<Click> = Vec::with_capacity(batch_size);
+            let mut interval = tokio::time::interval(flush_every);
+            loop {
+                let room = batch_size - batch.len();
+                tokio::select! {
+                    n = rx.recv_many(&mut batch, room) => {
+                        // every sender is gone
+                        if n == 0 {
+                            flush(&store, &mut batch).await;
+                            break;
+                        }
+                        if batch.len() >= batch_size {
+                            flush(&store, &mut batch).await;
+                        }
+                    }
+                    _ = interval.tick() => flush(&store, &mut batch).await,
+                }
+            }
+        });
+
+        Self {
+            tx,
+            salt: salt.into(),
+        }
+    }
+
+    /// Queue a click, dropped with a warning if the queue is full
+    pub fn record(
+        &self,
+        id: &str,
+        referrer: Option<String>,
+        user_agent: Option<String>,
+        ip: Option<IpAddr>,
+    ) {
+        let click = Click {
+            id: id.to_string(),
+            clicked_at: Utc::now(),
+            referrer,
+            user_agent,
+            ip_hash: ip.map(|ip| hash_ip(&self.salt, ip)),
+        };
+        if let Err(e) = self.tx.try_send(click) {
+            warn!("drop click for {id}: {e}");
+        }
+    }
+}
+
+async fn flush(store: &Arc<dyn UrlStore>, batch: &mut Vec<Click>) {
+    if batch.is_empty() {
+        return;
+    }
+    if let Err(e) = store.record_clicks(batch).await {
+        warn!("failed to record {} clicks: {e}", batch.len());
+    }
+    batch.clear();
+}
+
+/// The configured salt, or else a random one generated on first start and
+/// kept in the store. An unsalted hash of an ipv4 is easy to reverse
+pub async fn ip_hash_salt(store: &dyn UrlStore, configured: &str) -> Result<String, Error> {
+    if !configured.is_empty() {
+        return Ok(configured.to_string());
+    }
+    store.setting("ip_hash_salt", &nanoid::nanoid!(32)).await
+}
+
+/// Salted sha256 of the client ip, so visitors can be told apart without
+/// keeping their address
+pub fn hash_ip(salt: &str, ip: IpAddr) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(ip.to_string().as_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .take(16)
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{LinkOptions, MemoryStore};
+
+    #[test]
+    fn hash_ip_should_be_stable_and_salted() {
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        assert_eq!(hash_ip("a", ip), hash_ip("a", ip));
+        assert_ne!(hash_ip("a", ip), hash_ip("b", ip));
+        assert_eq!(hash_ip("a", ip).len(), 32);
+    }
+
+    #[tokio::test]
+    async fn salt_should_be_generated_once() {
+        let store = MemoryStore::new();
+        let salt = ip_hash_salt(&store, "").await.unwrap();
+        assert_eq!(salt.len(), 32);
+        assert_eq!(ip_hash_salt(&store, "").await.unwrap(), salt);
+        assert_eq!(ip_hash_salt(&store, "pepper").await.unwrap(), "pepper");
+    }
+
+    #[tokio::test]
+    async fn recorder_should_flush_batches() {
+        let store: Arc<dyn UrlStore> = Arc::new(MemoryStore::new());
+        store
+            .claim("abc", "https://example.com", &LinkOptions::default())
+            .await
+            .unwrap();
+
+        let recorder =
+            ClickRecorder::spawn_with(store.clone(), "", 16, 2, Duration::from_millis(10));
+        for referrer in ["https://a.com", "https://a.com", "https://b.com"] {
+            recorder.record("abc", Some(referrer.to_string()), None, None);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let stats = store.stats("abc").await.unwrap();
+        assert_eq!(stats.total, 3);
+        assert_eq!(stats.daily.len(), 1);
+        assert_eq!(stats.daily[0].clicks, 3);
+        assert_eq!(stats.top_referrers[0].referrer, "https://a.com");
+        assert_eq!(stats.top_referrers[0].clicks, 2);
+    }
+}
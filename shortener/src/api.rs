@@ -0,0 +1,165 @@
+use crate::auth::{hash_secret, require_admin, require_read, require_write, ApiKey, Scope};
+use crate::error::Error;
+use crate::redirect;
+use crate::store::{LinkPatch, UrlRecord};
+use crate::AppState;
+use axum::{
+    extract::{Path, Query, State},
+    middleware::from_fn_with_state,
+    response::IntoResponse,
+    routing::{get, patch, post},
+    Extension, Json, Router,
+};
+use chrono::{DateTime, Utc};
+use http::StatusCode;
+use serde::{Deserialize, Deserializer, Serialize};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+struct ListParams {
+    /// `next` of the previous page
+    after: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct ListRes {
+    links: Vec<UrlRecord>,
+    /// Pass as `after` to get the next page, unset on the last one
+    next: Option<String>,
+    limit: usize,
+}
+
+/// Absent fields are left untouched, `null` clears `expires_at`, `max_clicks`
+/// and `redirect_status`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UpdateReq {
+    url: Option<String>,
+    disabled: Option<bool>,
+    #[serde(default, deserialize_with = "nullable")]
+    expires_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "nullable")]
+    max_clicks: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    redirect_status: Option<Option<u16>>,
+    suspicious: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateKeyReq {
+    name: String,
+    scope: Scope,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateKeyRes {
+    #[serde(flatten)]
+    key: ApiKey,
+    /// Only shown once, the store keeps its hash
+    secret: String,
+}
+
+/// Tell a field set to `null` apart from a missing one
+fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+/// Management routes, mounted under `/api`
+pub fn router(state: AppState) -> Router<AppState> {
+    let read = Router::new()
+        .route("/links", get(list_links))
+        .route_layer(from_fn_with_state(state.clone(), require_read));
+    let write = Router::new()
+        .route("/links/:id", patch(update_link).delete(delete_link))
+        .route_layer(from_fn_with_state(state.clone(), require_write));
+    let admin = Router::new()
+        .route("/keys", post(create_key))
+        .route_layer(from_fn_with_state(state, require_admin));
+
+    read.merge(write).merge(admin)
+}
+
+async fn list_links(
+    State(state): State<AppState>,
+    Query(params): Query<ListParams>,
+) -> Result<impl IntoResponse, Error> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let links = state.store.list(params.after.as_deref(), limit).await?;
+    let next = match links.last() {
+        Some(last) if links.len() == limit => Some(last.id.clone()),
+        _ => None,
+    };
+    Ok(Json(ListRes { links, next, limit }))
+}
+
+async fn update_link(
+    State(state): State<AppState>,
+    Extension(key): Extension<ApiKey>,
+    Path(id): Path<String>,
+    Json(data): Json<UpdateReq>,
+) -> Result<impl IntoResponse, Error> {
+    key.require_owner(&state.store.resolve(&id).await?)?;
+    let patch = LinkPatch {
+        url: data
+            .url
+            .map(|url| state.policy.normalize(&url))
+            .transpose()?,
+        disabled: data.disabled,
+        expires_at: data.expires_at,
+        max_clicks: data.max_clicks.map(|v| v.map(i64::from)),
+        redirect_status: match data.redirect_status {
+            Some(Some(code)) => Some(Some(redirect::status(code)?.as_u16().into())),
+            Some(None) => Some(None),
+            None => None,
+        },
+        suspicious: data.suspicious,
+    };
+    let record = state.store.update(&id, &patch).await?;
+    Ok(Json(record))
+}
+
+async fn delete_link(
+    State(state): State<AppState>,
+    Extension(key): Extension<ApiKey>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    key.require_owner(&state.store.resolve(&id).await?)?;
+    state.store.delete(&id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn create_key(
+    State(state): State<AppState>,
+    Json(data): Json<CreateKeyReq>,
+) -> Result<impl IntoResponse, Error> {
+    let (key, secret) = ApiKey::generate(&data.name, data.scope);
+    state
+        .store
+        .create_api_key(&key, &hash_secret(&secret))
+        .await?;
+    Ok((StatusCode::CREATED, Json(CreateKeyRes { key, secret })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_req_should_tell_null_from_missing() {
+        let req: UpdateReq = serde_json::from_str(r#"{"max_clicks": null}"#).unwrap();
+        assert_eq!(req.max_clicks, Some(None));
+        assert_eq!(req.expires_at, None);
+
+        let req: UpdateReq =
+            serde_json::from_str(r#"{"max_clicks": 3, "disabled": true}"#).unwrap();
+        assert_eq!(req.max_clicks, Some(Some(3)));
+        assert_eq!(req.disabled, Some(true));
+    }
+}
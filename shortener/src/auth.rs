@@ -0,0 +1,215 @@
+use crate::error::Error;
+use crate::store::UrlRecord;
+use crate::AppState;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use http::header::AUTHORIZATION;
+use http::request::Parts;
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+/// What an api key may do, each scope includes the ones below it
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Scope::Read),
+            "write" => Ok(Scope::Write),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(Error::Unauthorized),
+        }
+    }
+}
+
+/// An authenticated api key, the secret itself is never stored
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub scope: Scope,
+}
+
+impl ApiKey {
+    /// Create a key with a fresh secret, returns the key and its secret
+    pub fn generate(name: &str, scope: Scope) -> (Self, String) {
+        let key = Self {
+            id: nanoid!(8),
+            name: name.to_string(),
+            scope,
+        };
+        (key, format!("sk_{}", nanoid!(32)))
+    }
+
+    fn require(&self, scope: Scope) -> Result<(), Error> {
+        if self.scope < scope {
+            return Err(Error::Forbidden);
+        }
+        Ok(())
+    }
+
+    /// Links can be changed by the key that created them, or by any admin key
+    pub fn require_owner(&self, record: &UrlRecord) -> Result<(), Error> {
+        if self.scope < Scope::Admin && record.owner.as_deref() != Some(self.id.as_str()) {
+            return Err(Error::Forbidden);
+        }
+        Ok(())
+    }
+}
+
+/// Keys are looked up by the sha256 of their secret
+pub fn hash_secret(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn bearer(parts: &Parts) -> Option<&str> {
+    parts
+        .headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+async fn authenticate(state: &AppState, parts: &Parts) -> Result<Option<ApiKey>, Error> {
+    match bearer(parts) {
+        Some(secret) => state
+            .store
+            .find_api_key(&hash_secret(secret))
+            .await
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+async fn authorize(
+    state: AppState,
+    req: Request,
+    next: Next,
+    scope: Scope,
+) -> Result<Response, Error> {
+    let (mut parts, body) = req.into_parts();
+    let key = authenticate(&state, &parts)
+        .await?
+        .ok_or(Error::Unauthorized)?;
+    key.require(scope)?;
+
+    parts.extensions.insert(key);
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
+
+pub async fn require_read(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    authorize(state, req, next, Scope::Read).await
+}
+
+pub async fn require_write(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    authorize(state, req, next, Scope::Write).await
+}
+
+pub async fn require_admin(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    authorize(state, req, next, Scope::Admin).await
+}
+
+/// Attach the caller's key if it sent one, anonymous requests pass through
+pub async fn identify(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let (mut parts, body) = req.into_parts();
+    if let Some(key) = authenticate(&state, &parts).await? {
+        key.require(Scope::Write)?;
+        parts.extensions.insert(key);
+    }
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_should_be_ordered() {
+        let (key, secret) = ApiKey::generate("ci", Scope::Write);
+        assert!(secret.starts_with("sk_"));
+        assert!(key.require(Scope::Read).is_ok());
+        assert!(key.require(Scope::Write).is_ok());
+        assert!(matches!(key.require(Scope::Admin), Err(Error::Forbidden)));
+        assert_eq!("admin".parse::<Scope>().unwrap(), Scope::Admin);
+    }
+
+    #[test]
+    fn owner_should_be_checked() {
+        let (key, _) = ApiKey::generate("ci", Scope::Write);
+        let (admin, _) = ApiKey::generate("ops", Scope::Admin);
+        let mut record = UrlRecord {
+            id: "abc".to_string(),
+            url: "https://a.com".to_string(),
+            expires_at: None,
+            max_clicks: None,
+            clicks: 0,
+            disabled: false,
+            owner: None,
+            redirect_status: None,
+            suspicious: false,
+        };
+        assert!(matches!(key.require_owner(&record), Err(Error::Forbidden)));
+        assert!(admin.require_owner(&record).is_ok());
+        record.owner = Some(key.id.clone());
+        assert!(key.require_owner(&record).is_ok());
+        assert!(admin.require_owner(&record).is_ok());
+    }
+
+    #[test]
+    fn hash_secret_should_be_hex_sha256() {
+        assert_eq!(
+            hash_secret("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use crate::auth::{require_read, require_write, ApiKey};
+use crate::error::Error;
+use crate::ratelimit::key_client;
+use crate::store::UrlRecord;
+use crate::{AppState, ShortenReq};
+use axum::{
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, Query, State},
+    middleware::from_fn_with_state,
+    response::IntoResponse,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use futures_util::stream;
+use http::{
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    HeaderMap,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Most rows a single bulk request may carry
+const MAX_ROWS: usize = 10_000;
+/// Bulk uploads are far larger than a single shorten request
+const MAX_BODY: usize = 16 * 1024 * 1024;
+/// Links fetched from the store per exported chunk
+const EXPORT_PAGE: usize = 500;
+
+#[derive(Debug, Serialize)]
+struct BulkRes {
+    results: Vec<RowResult>,
+}
+
+/// Outcome of one row, rows count from 1 and skip the csv header
+#[derive(Debug, Serialize)]
+struct RowResult {
+    row: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportParams {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+pub fn router(state: AppState) -> Router<AppState> {
+    let write = Router::new()
+        .route("/bulk", post(bulk))
+        .layer(DefaultBodyLimit::max(MAX_BODY))
+        .route_layer(from_fn_with_state(state.clone(), require_write));
+    let read = Router::new()
+        .route("/export", get(export))
+        .route_layer(from_fn_with_state(state, require_read));
+
+    write.merge(read)
+}
+
+/// Shorten every row of a JSON array or a CSV upload with a `url` column and
+/// optional `alias`, `expires_at` and `max_clicks` columns. A bad row does not
+/// fail the request, its error is reported in its result instead. Each row
+/// takes a token of the create rate limit. Aliases may be generated ids, so
+/// a `GET /export` dump imports again as it is
+async fn bulk(
+    State(state): State<AppState>,
+    Extension(key): Extension<ApiKey>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, Error> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let rows = if content_type.starts_with("text/csv") {
+        parse_csv(&body)
+    } else if content_type.starts_with("application/json") {
+        parse_json(&body)?
+    } else {
+        return Err(Error::InvalidBulk(format!(
+            "unsupported content type {content_type:?}, use application/json or text/csv"
+        )));
+    };
+    if rows.len() > MAX_ROWS {
+        return Err(Error::InvalidBulk(format!("more than {MAX_ROWS} rows")));
+    }
+    // every row is a create, bulk shares the create budget of the key
+    state.create_limit.take(&key_client(&key), rows.len())?;
+
+    let mut results = Vec::with_capacity(rows.len());
+    for (i, row) in rows.into_iter().enumerate() {
+        let ret = match row {
+            Ok(req) => state.create(&req, Some(key.id.clone()), true).await,
+            Err(e) => Err(e),
+        };
+        results.push(match ret {
+            Ok(id) => RowResult {
+                row: i + 1,
+                url: Some(state.link(&id)),
+                error: None,
+            },
+            Err(e) => RowResult {
+                row: i + 1,
+                url: None,
+                error: Some(e.to_string()),
+            },
+        });
+    }
+
+    Ok(Json(BulkRes { results }))
+}
+
+fn parse_json(body: &[u8]) -> Result<Vec<Result<ShortenReq, Error>>, Error> {
+    let values: Vec<Value> =
+        serde_json::from_slice(body).map_err(|e| Error::InvalidBulk(e.to_string()))?;
+    Ok(values
+        .into_iter()
+        .map(|v| serde_json::from_value(v).map_err(|e| Error::InvalidBulk(e.to_string())))
+        .collect())
+}
+
+fn parse_csv(body: &[u8]) -> Vec<Result<ShortenReq, Error>> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body)
+        .deserialize()
+        .map(|row| row.map_err(|e| Error::InvalidBulk(e.to_string())))
+        .collect()
+}
+
+/// Stream every link page by page, as CSV with a header row or as NDJSON
+async fn export(
+    State(state): State<AppState>,
+    Query(params): Query<ExportParams>,
+) -> impl IntoResponse {
+    let (content_type, disposition) = match params.format {
+        ExportFormat::Csv => ("text/csv", "attachment; filename=\"links.csv\""),
+        ExportFormat::Ndjson => (
+            "application/x-ndjson",
+            "attachment; filename=\"links.ndjson\"",
+        ),
+    };
+
+    // pages continue after the last id of the previous one, so links created
+    // or deleted during the export do not shift them
+    let chunks = stream::try_unfold(Some(None), move |page: Option<Option<String>>| {
+        let state = state.clone();
+        let format = params.format;
+        async move {
+            let Some(after) = page else {
+                return Ok(None);
+            };
+            let first = after.is_none();
+            let records = state.store.list(after.as_deref(), EXPORT_PAGE).await?;
+            if records.is_empty() && !first {
+                return Ok(None);
+            }
+            // a short page is the last one
+            let next = (records.len() == EXPORT_PAGE).then(|| records.last().map(|r| r.id.clone()));
+            let chunk = match format {
+                ExportFormat::Csv => to_csv(&records, first)?,
+                ExportFormat::Ndjson => to_ndjson(&records)?,
+            };
+            Ok::<_, Error>(Some((Bytes::from(chunk), next)))
+        }
+    });
+
+    (
+        [
+            (CONTENT_TYPE, content_type),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        Body::from_stream(chunks),
+    )
+}
+
+fn to_csv(records: &[UrlRecord], header: bool) -> Result<Vec<u8>, Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(header)
+        .from_writer(Vec::new());
+    if records.is_empty() && header {
+        writer.write_record(UrlRecord::COLUMNS)?;
+    }
+    for record in records {
+        writer.serialize(record)?;
+    }
+    writer
+        .into_inner()
+        .map_err(|e| csv::Error::from(e.into_error()).into())
+}
+
+fn to_ndjson(records: &[UrlRecord]) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    for record in records {
+        serde_json::to_writer(&mut buf, record)?;
+        buf.push(b'\n');
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_csv_should_report_bad_rows() {
+        let body = b"url,alias,max_clicks\nhttps://a.com,,\nhttps://b.com, vanity ,3\nhttps://c.com,,many\n";
+        let rows = parse_csv(body);
+        assert_eq!(rows.len(), 3);
+
+        let first = rows[0].as_ref().unwrap();
+        assert_eq!(first.url, "https://a.com");
+        assert!(first.alias.is_none());
+        let second = rows[1].as_ref().unwrap();
+        assert_eq!(second.alias.as_deref(), Some("vanity"));
+        assert_eq!(second.max_clicks, Some(3));
+        assert!(matches!(rows[2], Err(Error::InvalidBulk(_))));
+    }
+
+    #[test]
+    fn parse_json_should_report_bad_rows() {
+        let rows = parse_json(br#"[{"url": "https://a.com"}, {"alias": "x"}]"#).unwrap();
+        assert!(rows[0].is_ok());
+        assert!(rows[1].is_err());
+        assert!(parse_json(b"{}").is_err());
+    }
+
+    #[test]
+    fn exported_csv_should_import_again() {
+        let record = UrlRecord {
+            id: "abc".to_string(),
+            url: "https://a.com/".to_string(),
+            expires_at: None,
+            max_clicks: Some(5),
+            clicks: 2,
+            disabled: false,
+            owner: None,
+            redirect_status: Some(307),
+            suspicious: false,
+        };
+        let csv = to_csv(&[record], true).unwrap();
+        let rows = parse_csv(&csv);
+        let req = rows[0].as_ref().unwrap();
+        assert_eq!(req.alias.as_deref(), Some("abc"));
+        assert_eq!(req.max_clicks, Some(5));
+        assert_eq!(req.redirect_status, Some(307));
+
+        assert_eq!(
+            to_csv(&[], true).unwrap(),
+            b"id,url,expires_at,max_clicks,clicks,disabled,owner,redirect_status,suspicious\n"
+        );
+    }
+}
@@ -1,19 +1,101 @@
 use axum::response::{IntoResponse, Response};
-use http::StatusCode;
+use http::{
+    header::{RETRY_AFTER, WWW_AUTHENTICATE},
+    StatusCode,
+};
+use qrcode::types::QrError;
+use std::time::Duration;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("database error")]
     SqlxError(#[from] sqlx::error::Error),
+    #[error("csv error")]
+    CsvError(#[from] csv::Error),
+    #[error("json error")]
+    JsonError(#[from] serde_json::Error),
+    #[error("qr code error: {0}")]
+    QrError(#[from] QrError),
+    #[error("image error")]
+    ImageError(#[from] image::ImageError),
+    #[error("metrics error")]
+    MetricsError(#[from] prometheus::Error),
     #[error("url not found")]
     UrlNotFound,
+    #[error("url expired")]
+    UrlExpired,
+    #[error("url disabled")]
+    UrlDisabled,
+    #[error("url is already shortened as {0}")]
+    UrlConflict(String),
+    #[error("id already taken")]
+    IdConflict,
+    #[error("no free id left")]
+    IdSpaceExhausted,
+    #[error("invalid url: {0}")]
+    InvalidUrl(String),
+    #[error("invalid alias: {0}")]
+    InvalidAlias(String),
+    #[error("alias {0} is already in use")]
+    AliasConflict(String),
+    #[error("migration {0} was changed after it was applied")]
+    MigrationChanged(i64),
+    #[error("database has migration {0}, which this build does not know")]
+    UnknownMigration(i64),
+    #[error("unsupported store: {0}")]
+    UnsupportedStore(String),
+    #[error("missing or invalid api key")]
+    Unauthorized,
+    #[error("invalid redirect status {0}, use 301, 302, 307 or 308")]
+    InvalidRedirect(u16),
+    #[error("invalid bulk request: {0}")]
+    InvalidBulk(String),
+    #[error("api key scope is too narrow")]
+    Forbidden,
+    #[error("too many requests, retry in {0:?}")]
+    RateLimited(Duration),
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
         match self {
             Error::UrlNotFound => StatusCode::NOT_FOUND.into_response(),
+            Error::UrlExpired | Error::UrlDisabled => StatusCode::GONE.into_response(),
             Error::SqlxError(_) => StatusCode::UNPROCESSABLE_ENTITY.into_response(),
+            Error::InvalidUrl(_) => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+            Error::InvalidAlias(_) => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+            Error::InvalidRedirect(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Error::InvalidBulk(_) => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+            Error::AliasConflict(_) | Error::UrlConflict(_) => {
+                (StatusCode::CONFLICT, self.to_string()).into_response()
+            }
+            Error::Unauthorized => {
+                (StatusCode::UNAUTHORIZED, [(WWW_AUTHENTICATE, "Bearer")]).into_response()
+            }
+            Error::Forbidden => StatusCode::FORBIDDEN.into_response(),
+            Error::RateLimited(wait) => {
+                // Retry-After only takes whole seconds
+                let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+                let headers = [(RETRY_AFTER, secs.max(1).to_string())];
+                (StatusCode::TOO_MANY_REQUESTS, headers).into_response()
+            }
+            Error::IdSpaceExhausted => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+            // the link does not fit in the largest qr code
+            Error::QrError(QrError::DataTooLong) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, self.to_string()).into_response()
+            }
+            Error::IdConflict
+            | Error::UnsupportedStore(_)
+            | Error::MigrationChanged(_)
+            | Error::UnknownMigration(_)
+            | Error::CsvError(_)
+            | Error::JsonError(_)
+            | Error::QrError(_)
+            | Error::ImageError(_)
+            | Error::MetricsError(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
         }
     }
 }
@@ -0,0 +1,124 @@
+use axum::body::{to_bytes, Body, Bytes};
+use axum::response::Response;
+use axum::Router;
+use futures_util::future::BoxFuture;
+use http::{
+    header::{HeaderName, HeaderValue, CONTENT_TYPE},
+    HeaderMap, Method, Request, StatusCode,
+};
+use std::future::IntoFuture;
+use tower::ServiceExt;
+
+/// Drives a `Router` in process, every request goes through `oneshot`
+/// on a clone of the router, no socket involved
+pub(crate) struct TestClient {
+    app: Router,
+}
+
+impl TestClient {
+    pub(crate) fn new(app: Router) -> Self {
+        TestClient { app }
+    }
+
+    pub(crate) fn get(&self, url: &str) -> RequestBuilder {
+        self.request(Method::GET, url)
+    }
+
+    pub(crate) fn post(&self, url: &str) -> RequestBuilder {
+        self.request(Method::POST, url)
+    }
+
+    pub(crate) fn patch(&self, url: &str) -> RequestBuilder {
+        self.request(Method::PATCH, url)
+    }
+
+    pub(crate) fn delete(&self, url: &str) -> RequestBuilder {
+        self.request(Method::DELETE, url)
+    }
+
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        RequestBuilder {
+            app: self.app.clone(),
+            builder: Request::builder().method(method).uri(url),
+            body: Body::empty(),
+        }
+    }
+}
+
+pub(crate) struct RequestBuilder {
+    app: Router,
+    builder: http::request::Builder,
+    body: Body,
+}
+
+impl RequestBuilder {
+    pub(crate) fn body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub(crate) fn json<T>(mut self, json: &T) -> Self
+    where
+        T: serde::Serialize,
+    {
+        self.body = serde_json::to_vec(json).unwrap().into();
+        self.header(CONTENT_TYPE, "application/json")
+    }
+
+    pub(crate) fn header<K, V>(mut self, key: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: Into<http::Error>,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<http::Error>,
+    {
+        self.builder = self.builder.header(key, value);
+        self
+    }
+}
+
+impl IntoFuture for RequestBuilder {
+    type Output = TestResponse;
+    type IntoFuture = BoxFuture<'static, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            let req = self.builder.body(self.body).unwrap();
+            TestResponse {
+                response: self.app.oneshot(req).await.unwrap(),
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TestResponse {
+    response: Response,
+}
+
+impl TestResponse {
+    pub(crate) async fn bytes(self) -> Bytes {
+        to_bytes(self.response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+    }
+
+    pub(crate) async fn text(self) -> String {
+        String::from_utf8(self.bytes().await.to_vec()).unwrap()
+    }
+
+    pub(crate) async fn json<T>(self) -> T
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        serde_json::from_slice(&self.bytes().await).unwrap()
+    }
+
+    pub(crate) fn status(&self) -> StatusCode {
+        self.response.status()
+    }
+
+    pub(crate) fn headers(&self) -> &HeaderMap {
+        self.response.headers()
+    }
+}
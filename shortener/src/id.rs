@@ -0,0 +1,199 @@
+use crate::config::IdConfig;
+use crate::error::Error;
+use crate::store::UrlStore;
+use anyhow::ensure;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const BASE62: &[char] = &[
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I',
+    'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', 'a', 'b',
+    'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's', 't', 'u',
+    'v', 'w', 'x', 'y', 'z',
+];
+
+/// How ids are produced
+#[derive(Debug)]
+pub enum IdStrategy {
+    /// nanoid over the configured alphabet
+    Random { alphabet: Vec<char> },
+    /// base62 encoded counter, left padded to the configured length.
+    /// The counter starts at `start` and is moved past the ids already in
+    /// the store by `IdGenerator::resume`
+    Sequential { next: AtomicU64 },
+}
+
+/// IdGenerator produces candidate ids for new links, the caller retries
+/// at most `max_retries` times when a candidate is already taken
+#[derive(Debug)]
+pub struct IdGenerator {
+    length: usize,
+    max_retries: usize,
+    strategy: IdStrategy,
+}
+
+impl IdGenerator {
+    pub fn random(length: usize, alphabet: &str, max_retries: usize) -> anyhow::Result<Self> {
+        let mut alphabet: Vec<char> = alphabet.chars().collect();
+        alphabet.sort_unstable();
+        alphabet.dedup();
+        ensure!(length > 0, "id length must be positive");
+        ensure!(
+            (2..=256).contains(&alphabet.len()),
+            "id alphabet must have between 2 and 256 distinct characters"
+        );
+        // `/:id+` is the preview page of `id`, the others would end the path
+        // or need escaping
+        ensure!(
+            !alphabet
+                .iter()
+                .any(|c| matches!(c, '+' | '/' | '?' | '#' | '%') || c.is_whitespace()),
+            "id alphabet must not contain '+', '/', '?', '#', '%' or whitespace"
+        );
+
+        Ok(Self {
+            length,
+            max_retries,
+            strategy: IdStrategy::Random { alphabet },
+        })
+    }
+
+    pub fn sequential(length: usize, start: u64, max_retries: usize) -> anyhow::Result<Self> {
+        ensure!(length > 0, "id length must be positive");
+
+        Ok(Self {
+            length,
+            max_retries,
+            strategy: IdStrategy::Sequential {
+                next: AtomicU64::new(start),
+            },
+        })
+    }
+
+    pub fn from_config(config: &IdConfig) -> anyhow::Result<Self> {
+        match config.sequential_start {
+            Some(start) => Self::sequential(config.length, start, config.max_retries),
+            None => Self::random(config.length, &config.alphabet, config.max_retries),
+        }
+    }
+
+    /// Continue sequential ids after the highest one in `store`, so a
+    /// restarted server does not hand out taken ids again
+    pub async fn resume(&self, store: &dyn UrlStore) -> Result<(), Error> {
+        if let IdStrategy::Sequential { .. } = self.strategy {
+            if let Some(id) = store.max_id(self.length).await? {
+                self.skip_past(&id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Continue sequential ids after `id` if it looks like a generated one,
+    /// for links imported with the id another server gave them
+    pub fn skip_past(&self, id: &str) {
+        if let IdStrategy::Sequential { ref next } = self.strategy {
+            if let Some(n) = decode(id).filter(|_| id.len() == self.length) {
+                next.fetch_max(n.saturating_add(1), Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Whether `alias` looks like a generated id. Sequential ids resume from
+    /// the highest one stored, so such aliases would move the counter
+    pub fn shadows(&self, alias: &str) -> bool {
+        matches!(self.strategy, IdStrategy::Sequential { .. })
+            && alias.len() == self.length
+            && decode(alias).is_some()
+    }
+
+    pub fn max_retries(&self) -> usize {
+        self.max_retries
+    }
+
+    /// Produce the next candidate id
+    pub fn generate(&self) -> Result<String, Error> {
+        match self.strategy {
+            IdStrategy::Random { ref alphabet } => {
+                Ok(nanoid::format(nanoid::rngs::default, alphabet, self.length))
+            }
+            IdStrategy::Sequential { ref next } => {
+                let n = next.fetch_add(1, Ordering::Relaxed);
+                base62(n, self.length).ok_or(Error::IdSpaceExhausted)
+            }
+        }
+    }
+}
+
+/// Encode `n` in base62 padded to `length`, `None` if it does not fit
+fn base62(mut n: u64, length: usize) -> Option<String> {
+    let mut buf = vec![BASE62[0]; length];
+    for c in buf.iter_mut().rev() {
+        *c = BASE62[(n % 62) as usize];
+        n /= 62;
+    }
+    if n > 0 {
+        return None;
+    }
+    Some(buf.into_iter().collect())
+}
+
+/// Inverse of `base62`, `None` for other characters or on overflow
+fn decode(id: &str) -> Option<u64> {
+    id.chars().try_fold(0u64, |n, c| {
+        let digit = BASE62.iter().position(|&d| d == c)? as u64;
+        n.checked_mul(62)?.checked_add(digit)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{LinkOptions, MemoryStore};
+
+    #[test]
+    fn random_id_should_respect_length_and_alphabet() {
+        let ids = IdGenerator::random(10, "abc", 3).unwrap();
+        let id = ids.generate().unwrap();
+        assert_eq!(id.len(), 10);
+        assert!(id.chars().all(|c| "abc".contains(c)));
+
+        assert!(IdGenerator::random(6, "aaa", 3).is_err());
+        assert!(IdGenerator::random(0, "abc", 3).is_err());
+        for bad in ["ab+", "ab/", "ab?", "ab#", "ab%", "ab "] {
+            assert!(IdGenerator::random(6, bad, 3).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn sequential_id_should_be_base62() {
+        let ids = IdGenerator::sequential(2, 61, 3).unwrap();
+        assert_eq!(ids.generate().unwrap(), "0z");
+        assert_eq!(ids.generate().unwrap(), "10");
+
+        let ids = IdGenerator::sequential(2, 62 * 62 - 1, 3).unwrap();
+        assert_eq!(ids.generate().unwrap(), "zz");
+        assert!(matches!(ids.generate(), Err(Error::IdSpaceExhausted)));
+    }
+
+    #[tokio::test]
+    async fn sequential_id_should_resume_from_store() {
+        let store = MemoryStore::new();
+        let opts = LinkOptions::default();
+        store.claim("0z", "https://a.com", &opts).await.unwrap();
+        store.claim("1-", "https://b.com", &opts).await.unwrap();
+        store.claim("abc", "https://c.com", &opts).await.unwrap();
+
+        let ids = IdGenerator::sequential(2, 0, 3).unwrap();
+        ids.resume(&store).await.unwrap();
+        assert_eq!(ids.generate().unwrap(), "10");
+
+        // a configured start past the stored ids wins
+        let ids = IdGenerator::sequential(2, 100, 3).unwrap();
+        ids.resume(&store).await.unwrap();
+        assert_eq!(ids.generate().unwrap(), "1c");
+
+        assert!(ids.shadows("Ab"));
+        assert!(!ids.shadows("A-"));
+        assert!(!ids.shadows("abc"));
+        assert!(!IdGenerator::random(2, "abc", 3).unwrap().shadows("ab"));
+    }
+}
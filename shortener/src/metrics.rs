@@ -0,0 +1,136 @@
+use crate::error::Error;
+use crate::store::UrlStore;
+use crate::AppState;
+use axum::extract::{MatchedPath, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::time::Instant;
+
+/// Metrics collects what `/metrics` reports in the Prometheus text format
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    latency: HistogramVec,
+    /// Successful shorten requests, bulk rows included
+    pub shortened: IntCounter,
+    /// Redirects served
+    pub redirects: IntCounter,
+    pool_connections: IntGaugeVec,
+    pool_max: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+        let requests = IntCounterVec::new(
+            Opts::new("shortener_http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )?;
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "shortener_http_request_duration_seconds",
+                "HTTP request latency",
+            ),
+            &["method", "route"],
+        )?;
+        let shortened = IntCounter::new("shortener_shorten_total", "Links shortened")?;
+        let redirects = IntCounter::new("shortener_redirects_total", "Redirects served")?;
+        let pool_connections = IntGaugeVec::new(
+            Opts::new(
+                "shortener_db_pool_connections",
+                "Database pool connections by state",
+            ),
+            &["state"],
+        )?;
+        let pool_max = IntGauge::new(
+            "shortener_db_pool_max_connections",
+            "Size limit of the database pool",
+        )?;
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(latency.clone()))?;
+        registry.register(Box::new(shortened.clone()))?;
+        registry.register(Box::new(redirects.clone()))?;
+        registry.register(Box::new(pool_connections.clone()))?;
+        registry.register(Box::new(pool_max.clone()))?;
+
+        Ok(Self {
+            registry,
+            requests,
+            latency,
+            shortened,
+            redirects,
+            pool_connections,
+            pool_max,
+        })
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Encode every metric, pool gauges are sampled from `store` on the spot
+    pub fn render(&self, store: &dyn UrlStore) -> Result<String, Error> {
+        if let Some(pool) = store.pool_stats() {
+            let in_use = pool.size.saturating_sub(pool.idle);
+            self.pool_connections
+                .with_label_values(&["idle"])
+                .set(pool.idle.into());
+            self.pool_connections
+                .with_label_values(&["in_use"])
+                .set(in_use.into());
+            self.pool_max.set(pool.max.into());
+        }
+        Ok(TextEncoder::new().encode_to_string(&self.registry.gather())?)
+    }
+}
+
+/// Count and time every request by its route pattern, so `/:id` stays one series
+pub async fn track(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+
+    let start = Instant::now();
+    let res = next.run(req).await;
+
+    let metrics = &state.metrics;
+    metrics
+        .latency
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+    metrics
+        .requests
+        .with_label_values(&[&method, &route, res.status().as_str()])
+        .inc();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn render_should_include_counters() {
+        let metrics = Metrics::new().unwrap();
+        metrics.shortened.inc();
+        metrics
+            .requests
+            .with_label_values(&["GET", "/:id", "308"])
+            .inc();
+
+        let text = metrics.render(&MemoryStore::new()).unwrap();
+        assert!(text.contains("shortener_shorten_total 1"));
+        assert!(text.contains(
+            r#"shortener_http_requests_total{method="GET",route="/:id",status="308"} 1"#
+        ));
+    }
+}
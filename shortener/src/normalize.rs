@@ -0,0 +1,142 @@
+use crate::config::UrlConfig;
+use crate::error::Error;
+use url::Url;
+
+const MAX_URL_LEN: usize = 2048;
+
+/// UrlPolicy validates urls before they are stored and rewrites them into a
+/// canonical form, so equivalent urls end up under the same id
+#[derive(Debug, Default)]
+pub struct UrlPolicy {
+    sort_query: bool,
+    blocklist: Vec<String>,
+}
+
+impl UrlPolicy {
+    pub fn from_config(config: &UrlConfig) -> Self {
+        Self {
+            sort_query: config.sort_query,
+            blocklist: config
+                .blocklist
+                .iter()
+                .map(|d| d.trim_matches('.').to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Only absolute http(s) urls with a host are accepted. The host is
+    /// lowercased and default ports are dropped, query params are sorted
+    /// if `sort_query` is set
+    pub fn normalize(&self, raw: &str) -> Result<String, Error> {
+        if raw.len() > MAX_URL_LEN {
+            return Err(Error::InvalidUrl(format!(
+                "longer than {MAX_URL_LEN} bytes"
+            )));
+        }
+        let mut url = Url::parse(raw.trim()).map_err(|e| Error::InvalidUrl(e.to_string()))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(Error::InvalidUrl(format!(
+                "unsupported scheme {}",
+                url.scheme()
+            )));
+        }
+        // the url crate already lowercases the host and drops default ports
+        // of special schemes such as http and https
+        let host = url
+            .host_str()
+            .ok_or_else(|| Error::InvalidUrl("missing host".to_string()))?
+            .trim_end_matches('.')
+            .to_string();
+        if self.is_blocked(&host) {
+            return Err(Error::InvalidUrl(format!("{host} is blocked")));
+        }
+
+        if let Some(query) = url.query().filter(|_| self.sort_query) {
+            // sort the raw params, decoding and encoding them again would turn
+            // `%20` into `+` and `?flag` into `?flag=`. The sort is stable on
+            // the key, repeated keys keep their order
+            let mut params: Vec<&str> = query.split('&').filter(|p| !p.is_empty()).collect();
+            params.sort_by_key(|p| p.split('=').next());
+            let query = params.join("&");
+            url.set_query(Some(&query));
+        }
+        if url.query() == Some("") {
+            url.set_query(None);
+        }
+
+        Ok(url.into())
+    }
+
+    /// `host` or any of its parent domains is in the blocklist
+    fn is_blocked(&self, host: &str) -> bool {
+        self.blocklist.iter().any(|d| {
+            host == d
+                || host
+                    .strip_suffix(d.as_str())
+                    .is_some_and(|rest| rest.ends_with('.'))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(sort_query: bool) -> UrlPolicy {
+        UrlPolicy::from_config(&UrlConfig {
+            sort_query,
+            blocklist: vec!["evil.com".to_string()],
+        })
+    }
+
+    #[test]
+    fn normalize_should_canonicalize() {
+        let p = policy(false);
+        assert_eq!(
+            p.normalize("HTTP://Example.COM:80/a?b=1&a=2").unwrap(),
+            "http://example.com/a?b=1&a=2"
+        );
+        assert_eq!(
+            p.normalize("https://example.com:443").unwrap(),
+            "https://example.com/"
+        );
+        assert_eq!(
+            p.normalize("https://example.com:8443/x").unwrap(),
+            "https://example.com:8443/x"
+        );
+
+        let p = policy(true);
+        assert_eq!(
+            p.normalize("https://example.com/a?b=1&a=2").unwrap(),
+            "https://example.com/a?a=2&b=1"
+        );
+        assert_eq!(
+            p.normalize("https://example.com/?q=a%20b&flag&c=2&a=x+y&c=1")
+                .unwrap(),
+            "https://example.com/?a=x+y&c=2&c=1&flag&q=a%20b"
+        );
+        assert_eq!(
+            p.normalize("https://example.com/?&").unwrap(),
+            "https://example.com/"
+        );
+    }
+
+    #[test]
+    fn normalize_should_reject_bad_urls() {
+        let p = policy(false);
+        for url in [
+            "example.com",
+            "/relative",
+            "ftp://example.com",
+            "javascript:alert(1)",
+            "https://evil.com/x",
+            "https://www.Evil.com/x",
+        ] {
+            assert!(
+                matches!(p.normalize(url), Err(Error::InvalidUrl(_))),
+                "{url}"
+            );
+        }
+        assert!(p.normalize("https://notevil.com").is_ok());
+    }
+}
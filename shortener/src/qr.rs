@@ -0,0 +1,94 @@
+use crate::error::Error;
+use crate::AppState;
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+};
+use http::header::CONTENT_TYPE;
+use image::{ImageFormat, Luma};
+use qrcode::{render::svg, QrCode};
+use serde::Deserialize;
+use std::io::Cursor;
+
+const DEFAULT_SIZE: u32 = 256;
+const MIN_SIZE: u32 = 64;
+const MAX_SIZE: u32 = 2048;
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QrParams {
+    /// Minimum width and height in pixels, quiet zone included
+    size: Option<u32>,
+    #[serde(default)]
+    format: QrFormat,
+}
+
+/// QR code of the short link of `id`, dead links get the same errors as a redirect
+pub async fn qr(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<QrParams>,
+) -> Result<impl IntoResponse, Error> {
+    state.peek(&id).await?;
+
+    let size = params
+        .size
+        .unwrap_or(DEFAULT_SIZE)
+        .clamp(MIN_SIZE, MAX_SIZE);
+    let code = QrCode::new(state.link(&id))?;
+    let (content_type, body) = match params.format {
+        QrFormat::Png => ("image/png", render_png(&code, size)?),
+        QrFormat::Svg => ("image/svg+xml", render_svg(&code, size).into_bytes()),
+    };
+    Ok(([(CONTENT_TYPE, content_type)], body))
+}
+
+fn render_png(code: &QrCode, size: u32) -> Result<Vec<u8>, Error> {
+    let img = code.render::<Luma<u8>>().min_dimensions(size, size).build();
+    let mut buf = Cursor::new(Vec::new());
+    img.write_to(&mut buf, ImageFormat::Png)?;
+    Ok(buf.into_inner())
+}
+
+fn render_svg(code: &QrCode, size: u32) -> String {
+    code.render::<svg::Color>()
+        .min_dimensions(size, size)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qr_should_render_png_and_svg() {
+        let code = QrCode::new("http://127.0.0.1:5000/abc").unwrap();
+
+        let png = render_png(&code, 200).unwrap();
+        let img = image::load_from_memory_with_format(&png, ImageFormat::Png).unwrap();
+        assert!(img.width() >= 200);
+        assert_eq!(img.width(), img.height());
+
+        let svg = render_svg(&code, 200);
+        assert!(svg.contains("<svg"));
+    }
+
+    #[test]
+    fn too_long_links_should_be_unprocessable() {
+        let Err(err) = QrCode::new("a".repeat(8000)) else {
+            panic!("expected the data to be too long");
+        };
+        let err = Error::from(err);
+        assert_eq!(
+            err.into_response().status(),
+            http::StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use crate::auth::ApiKey;
+use crate::config::LimitConfig;
+use crate::error::Error;
+use crate::AppState;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Extension;
+use dashmap::DashMap;
+use http::header::FORWARDED;
+use http::{HeaderMap, HeaderName};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// RateLimiter hands out one token bucket per client. Buckets hold up to
+/// `burst` tokens and refill at `per_second`, every request takes one and
+/// bulk requests one per row
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: DashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    /// A limiter with a zero `per_second` lets everything through
+    pub fn from_config(config: &LimitConfig) -> Self {
+        Self {
+            per_second: config.per_second,
+            burst: f64::from(config.burst.max(1)),
+            buckets: DashMap::new(),
+        }
+    }
+
+    pub fn check(&self, client: &str) -> Result<(), Error> {
+        self.take(client, 1)
+    }
+
+    /// Take `n` tokens at once. More than `burst` go through on a full
+    /// bucket and leave it in debt, so the client waits until it is repaid
+    pub fn take(&self, client: &str, n: usize) -> Result<(), Error> {
+        self.take_at(client, n, Instant::now())
+    }
+
+    fn take_at(&self, client: &str, n: usize, now: Instant) -> Result<(), Error> {
+        if self.per_second <= 0.0 {
+            return Ok(());
+        }
+
+        let mut bucket = self.buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated_at: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.per_second).min(self.burst);
+        bucket.updated_at = now;
+
+        let n = n as f64;
+        let needed = n.min(self.burst);
+        if bucket.tokens >= needed {
+            bucket.tokens -= n;
+            return Ok(());
+        }
+        let wait = (needed - bucket.tokens) / self.per_second;
+        Err(Error::RateLimited(
+            Duration::try_from_secs_f64(wait).unwrap_or(Duration::MAX),
+        ))
+    }
+
+    /// Drop buckets that refilled completely, they are the same as new ones
+    pub fn sweep(&self) {
+        let now = Instant::now();
+        self.buckets.retain(|_, b| {
+            let elapsed = now.saturating_duration_since(b.updated_at);
+            b.tokens + elapsed.as_secs_f64() * self.per_second < self.burst
+        });
+    }
+}
+
+/// Periodically sweep idle buckets of `limiters` in the background
+pub fn spawn_sweeper(limiters: Vec<Arc<RateLimiter>>, every: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            for limiter in limiters.iter() {
+                limiter.sweep();
+            }
+        }
+    });
+}
+
+/// Address of the client: the last entry of the trusted `header` if it is
+/// set and parses, the peer address otherwise
+pub fn client_ip(
+    header: Option<&HeaderName>,
+    headers: &HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
+) -> Option<IpAddr> {
+    header
+        .and_then(|name| forwarded_ip(name, headers))
+        .or(addr.map(|ConnectInfo(addr)| addr.ip()))
+}
+
+/// The proxy appends the address it saw, everything before came from the client
+fn forwarded_ip(name: &HeaderName, headers: &HeaderMap) -> Option<IpAddr> {
+    let value = headers.get_all(name).iter().next_back()?.to_str().ok()?;
+    let last = value.rsplit(',').next()?.trim();
+    let addr = if name == FORWARDED {
+        // `for=192.0.2.1;proto=https` or `for="[2001:db8::1]:443"`
+        last.split(';').find_map(|pair| {
+            let (k, v) = pair.trim().split_once('=')?;
+            k.eq_ignore_ascii_case("for").then_some(v)
+        })?
+    } else {
+        last
+    };
+    let addr = addr.trim_matches('"');
+    addr.parse::<IpAddr>()
+        .ok()
+        .or_else(|| addr.parse::<SocketAddr>().ok().map(|a| a.ip()))
+        .or_else(|| addr.strip_prefix('[')?.split(']').next()?.parse().ok())
+}
+
+/// Bucket of requests made with `key`
+pub fn key_client(key: &ApiKey) -> String {
+    format!("key:{}", key.id)
+}
+
+/// Clients with an api key are limited per key, everyone else per ip
+fn client(state: &AppState, key: Option<Extension<ApiKey>>, req: &Request) -> String {
+    if let Some(Extension(key)) = key {
+        return key_client(&key);
+    }
+    let addr = req.extensions().get::<ConnectInfo<SocketAddr>>().copied();
+    match client_ip(state.client_ip_header.as_ref(), req.headers(), addr) {
+        Some(ip) => format!("ip:{ip}"),
+        None => "unknown".to_string(),
+    }
+}
+
+pub async fn limit_create(
+    State(state): State<AppState>,
+    key: Option<Extension<ApiKey>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    state.create_limit.check(&client(&state, key, &req))?;
+    Ok(next.run(req).await)
+}
+
+pub async fn limit_redirect(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    state.redirect_limit.check(&client(&state, None, &req))?;
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(per_second: f64, burst: u32) -> RateLimiter {
+        RateLimiter::from_config(&LimitConfig { per_second, burst })
+    }
+
+    #[test]
+    fn bucket_should_refill() {
+        let limiter = limiter(2.0, 2);
+        let now = Instant::now();
+        assert!(limiter.take_at("a", 1, now).is_ok());
+        assert!(limiter.take_at("a", 1, now).is_ok());
+        match limiter.take_at("a", 1, now) {
+            Err(Error::RateLimited(wait)) => assert_eq!(wait, Duration::from_millis(500)),
+            ret => panic!("expected RateLimited, got {ret:?}"),
+        }
+        // other clients have their own bucket
+        assert!(limiter.take_at("b", 1, now).is_ok());
+
+        let later = now + Duration::from_millis(500);
+        assert!(limiter.take_at("a", 1, later).is_ok());
+        assert!(limiter.take_at("a", 1, later).is_err());
+    }
+
+    #[test]
+    fn large_batches_should_leave_debt() {
+        let limiter = limiter(1.0, 4);
+        let now = Instant::now();
+        assert!(limiter.take_at("a", 3, now).is_ok());
+        // one token left, a batch waits for the rest
+        match limiter.take_at("a", 3, now) {
+            Err(Error::RateLimited(wait)) => assert_eq!(wait, Duration::from_secs(2)),
+            ret => panic!("expected RateLimited, got {ret:?}"),
+        }
+
+        // more than the burst on a full bucket goes through once
+        let later = now + Duration::from_secs(3);
+        assert!(limiter.take_at("a", 10, later).is_ok());
+        match limiter.take_at("a", 1, later) {
+            Err(Error::RateLimited(wait)) => assert_eq!(wait, Duration::from_secs(7)),
+            ret => panic!("expected RateLimited, got {ret:?}"),
+        }
+    }
+
+    #[test]
+    fn zero_rate_should_disable_limit() {
+        let limiter = limiter(0.0, 1);
+        for _ in 0..10 {
+            assert!(limiter.check("a").is_ok());
+        }
+        assert!(limiter.buckets.is_empty());
+    }
+
+    #[test]
+    fn client_ip_should_trust_only_the_proxy_entry() {
+        let peer = Some(ConnectInfo("10.0.0.1:4000".parse().unwrap()));
+        let xff = HeaderName::from_static("x-forwarded-for");
+        let mut headers = HeaderMap::new();
+        headers.insert(&xff, "6.6.6.6, 203.0.113.7".parse().unwrap());
+        headers.insert(
+            FORWARDED,
+            "for=6.6.6.6, for=\"[2001:db8::1]:443\";proto=https"
+                .parse()
+                .unwrap(),
+        );
+
+        let ip = |header: Option<&HeaderName>, headers: &HeaderMap| {
+            client_ip(header, headers, peer).unwrap().to_string()
+        };
+        assert_eq!(ip(None, &headers), "10.0.0.1");
+        assert_eq!(ip(Some(&xff), &headers), "203.0.113.7");
+        assert_eq!(ip(Some(&FORWARDED), &headers), "2001:db8::1");
+        assert_eq!(ip(Some(&xff), &HeaderMap::new()), "10.0.0.1");
+    }
+}
@@ -0,0 +1,85 @@
+use crate::error::Error;
+use http::StatusCode;
+
+/// Statuses a link may redirect with
+const ALLOWED: [StatusCode; 4] = [
+    StatusCode::MOVED_PERMANENTLY,
+    StatusCode::FOUND,
+    StatusCode::TEMPORARY_REDIRECT,
+    StatusCode::PERMANENT_REDIRECT,
+];
+
+/// Check that `code` is one of 301, 302, 307 or 308
+pub fn status(code: u16) -> Result<StatusCode, Error> {
+    ALLOWED
+        .into_iter()
+        .find(|s| s.as_u16() == code)
+        .ok_or(Error::InvalidRedirect(code))
+}
+
+/// Page shown instead of redirecting, the visitor has to follow the link
+/// themselves. `public_url` is the short link the page was served for
+pub fn preview_page(public_url: &str, url: &str, suspicious: bool) -> String {
+    let warning = if suspicious {
+        r#"<p class="warn">This link was flagged as suspicious. Only continue if you trust the destination.</p>"#
+    } else {
+        ""
+    };
+    let short = escape(public_url);
+    let url = escape(url);
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="robots" content="noindex">
+<title>Preview of {short}</title>
+<style>body{{font-family:sans-serif;max-width:40em;margin:4em auto}}code{{word-break:break-all}}.warn{{color:#b00}}</style>
+</head>
+<body>
+<h1>{short}</h1>
+{warning}
+<p>This link leads to:</p>
+<p><code>{url}</code></p>
+<p><a href="{url}" rel="noopener noreferrer nofollow">Continue to the destination</a></p>
+</body>
+</html>
+"#
+    )
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_should_only_allow_redirects() {
+        assert_eq!(status(302).unwrap(), StatusCode::FOUND);
+        assert_eq!(status(308).unwrap(), StatusCode::PERMANENT_REDIRECT);
+        assert!(matches!(status(200), Err(Error::InvalidRedirect(200))));
+        assert!(matches!(status(303), Err(Error::InvalidRedirect(303))));
+    }
+
+    #[test]
+    fn preview_page_should_escape_urls() {
+        let page = preview_page("https://sho.rt/abc", r#"https://a.com/?q="><script>"#, true);
+        assert!(page.contains("https://a.com/?q=&quot;&gt;&lt;script&gt;"));
+        assert!(!page.contains("<script>"));
+        assert!(page.contains("suspicious"));
+    }
+}
@@ -0,0 +1,291 @@
+use super::{LinkOptions, LinkPatch, PoolStats, UrlRecord, UrlStore};
+use crate::analytics::{Click, LinkStats};
+use crate::auth::ApiKey;
+use crate::error::Error;
+use async_trait::async_trait;
+use chrono::Utc;
+use lru::LruCache;
+use prometheus::{IntCounter, Registry};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Hit and miss counters of a `CachedStore`
+#[derive(Debug)]
+pub struct CacheStats {
+    hits: IntCounter,
+    misses: IntCounter,
+}
+
+impl Default for CacheStats {
+    fn default() -> Self {
+        Self {
+            hits: IntCounter::new("shortener_cache_hits_total", "Redirects served from cache")
+                .expect("valid metric"),
+            misses: IntCounter::new(
+                "shortener_cache_misses_total",
+                "Redirects that missed cache",
+            )
+            .expect("valid metric"),
+        }
+    }
+}
+
+impl CacheStats {
+    #[cfg(test)]
+    pub fn hits(&self) -> u64 {
+        self.hits.get()
+    }
+
+    #[cfg(test)]
+    pub fn misses(&self) -> u64 {
+        self.misses.get()
+    }
+
+    /// Export the counters through `registry`
+    pub fn register(&self, registry: &Registry) -> Result<(), prometheus::Error> {
+        registry.register(Box::new(self.hits.clone()))?;
+        registry.register(Box::new(self.misses.clone()))
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    record: UrlRecord,
+    cached_at: Instant,
+}
+
+/// CachedStore keeps recently visited links in an LRU in front of another store.
+///
+/// Links with a click budget are never cached, every visit has to be counted
+/// by the backend. Visits served from cache are counted when the next batch
+/// of clicks is recorded.
+pub struct CachedStore {
+    inner: Arc<dyn UrlStore>,
+    cache: Mutex<LruCache<String, Entry>>,
+    ttl: Duration,
+    stats: Arc<CacheStats>,
+    // id -> visits served from cache the backend has not counted yet
+    uncounted: Mutex<HashMap<String, i64>>,
+}
+
+impl CachedStore {
+    pub fn new(inner: Arc<dyn UrlStore>, size: NonZeroUsize, ttl: Duration) -> Self {
+        Self {
+            inner,
+            cache: Mutex::new(LruCache::new(size)),
+            ttl,
+            stats: Arc::new(CacheStats::default()),
+            uncounted: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn cache_stats(&self) -> Arc<CacheStats> {
+        self.stats.clone()
+    }
+
+    fn get(&self, id: &str) -> Option<UrlRecord> {
+        let mut cache = self.cache.lock().unwrap();
+        let entry = cache.get(id)?;
+        if entry.cached_at.elapsed() >= self.ttl || entry.record.is_expired(Utc::now()) {
+            cache.pop(id);
+            return None;
+        }
+        Some(entry.record.clone())
+    }
+
+    fn put(&self, record: &UrlRecord) {
+        if record.max_clicks.is_some() {
+            return;
+        }
+        let entry = Entry {
+            record: record.clone(),
+            cached_at: Instant::now(),
+        };
+        self.cache.lock().unwrap().put(record.id.clone(), entry);
+    }
+
+    fn invalidate(&self, id: &str) {
+        self.cache.lock().unwrap().pop(id);
+    }
+}
+
+#[async_trait]
+impl UrlStore for CachedStore {
+    async fn shorten(&self, id: &str, url: &str, opts: &LinkOptions) -> Result<String, Error> {
+        let ret = self.inner.shorten(id, url, opts).await;
+        self.invalidate(id);
+        ret
+    }
+
+    async fn claim(&self, id: &str, url: &str, opts: &LinkOptions) -> Result<(), Error> {
+        let ret = self.inner.claim(id, url, opts).await;
+        self.invalidate(id);
+        ret
+    }
+
+    async fn resolve(&self, id: &str) -> Result<UrlRecord, Error> {
+        self.inner.resolve(id).await
+    }
+
+    async fn visit(&self, id: &str) -> Result<UrlRecord, Error> {
+        if let Some(record) = self.get(id) {
+            self.stats.hits.inc();
+            *self
+                .uncounted
+                .lock()
+                .unwrap()
+                .entry(record.id.clone())
+                .or_default() += 1;
+            return Ok(record);
+        }
+
+        self.stats.misses.inc();
+        let record = self.inner.visit(id).await?;
+        self.put(&record);
+        Ok(record)
+    }
+
+    async fn record_clicks(&self, clicks: &[Click]) -> Result<(), Error> {
+        let uncounted = std::mem::take(&mut *self.uncounted.lock().unwrap());
+        for (id, n) in uncounted {
+            match self.inner.add_clicks(&id, n).await {
+                Ok(()) | Err(Error::UrlNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.inner.record_clicks(clicks).await
+    }
+
+    async fn add_clicks(&self, id: &str, n: i64) -> Result<(), Error> {
+        self.inner.add_clicks(id, n).await
+    }
+
+    async fn stats(&self, id: &str) -> Result<LinkStats, Error> {
+        self.inner.stats(id).await
+    }
+
+    async fn purge_expired(&self) -> Result<u64, Error> {
+        // expired entries are already dropped on lookup
+        self.inner.purge_expired().await
+    }
+
+    async fn update(&self, id: &str, patch: &LinkPatch) -> Result<UrlRecord, Error> {
+        let ret = self.inner.update(id, patch).await;
+        self.invalidate(id);
+        ret
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), Error> {
+        let ret = self.inner.delete(id).await;
+        self.invalidate(id);
+        ret
+    }
+
+    async fn list(&self, after: Option<&str>, limit: usize) -> Result<Vec<UrlRecord>, Error> {
+        self.inner.list(after, limit).await
+    }
+
+    async fn max_id(&self, length: usize) -> Result<Option<String>, Error> {
+        self.inner.max_id(length).await
+    }
+
+    async fn create_api_key(&self, key: &ApiKey, hash: &str) -> Result<(), Error> {
+        self.inner.create_api_key(key, hash).await
+    }
+
+    async fn find_api_key(&self, hash: &str) -> Result<ApiKey, Error> {
+        self.inner.find_api_key(hash).await
+    }
+
+    async fn setting(&self, name: &str, value: &str) -> Result<String, Error> {
+        self.inner.setting(name, value).await
+    }
+
+    async fn migrate(&self) -> Result<Vec<i64>, Error> {
+        self.inner.migrate().await
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        self.inner.ping().await
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.inner.pool_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    fn cached(ttl: Duration) -> CachedStore {
+        CachedStore::new(
+            Arc::new(MemoryStore::new()),
+            NonZeroUsize::new(2).unwrap(),
+            ttl,
+        )
+    }
+
+    #[tokio::test]
+    async fn visit_should_hit_cache() {
+        let store = cached(Duration::from_secs(60));
+        let opts = LinkOptions::default();
+        store.claim("abc", "https://a.com", &opts).await.unwrap();
+
+        assert_eq!(store.visit("abc").await.unwrap().url, "https://a.com");
+        assert_eq!(store.visit("abc").await.unwrap().url, "https://a.com");
+        assert_eq!(store.stats.misses(), 1);
+        assert_eq!(store.stats.hits(), 1);
+
+        store.delete("abc").await.unwrap();
+        assert!(matches!(store.visit("abc").await, Err(Error::UrlNotFound)));
+        assert_eq!(store.stats.misses(), 2);
+    }
+
+    #[tokio::test]
+    async fn hits_should_be_counted_with_the_next_clicks() {
+        let store = cached(Duration::from_secs(60));
+        let opts = LinkOptions::default();
+        store.claim("abc", "https://a.com", &opts).await.unwrap();
+
+        for _ in 0..3 {
+            store.visit("abc").await.unwrap();
+        }
+        assert_eq!(store.stats.hits(), 2);
+        assert_eq!(store.inner.resolve("abc").await.unwrap().clicks, 1);
+
+        store.record_clicks(&[]).await.unwrap();
+        assert_eq!(store.inner.resolve("abc").await.unwrap().clicks, 3);
+        store.record_clicks(&[]).await.unwrap();
+        assert_eq!(store.inner.resolve("abc").await.unwrap().clicks, 3);
+    }
+
+    #[tokio::test]
+    async fn capped_links_should_not_be_cached() {
+        let store = cached(Duration::from_secs(60));
+        let opts = LinkOptions {
+            max_clicks: Some(1),
+            ..Default::default()
+        };
+        store.claim("once", "https://a.com", &opts).await.unwrap();
+
+        assert!(store.visit("once").await.is_ok());
+        assert!(matches!(store.visit("once").await, Err(Error::UrlExpired)));
+        assert_eq!(store.stats.hits(), 0);
+    }
+
+    #[tokio::test]
+    async fn stale_entries_should_be_refreshed() {
+        let store = cached(Duration::ZERO);
+        let opts = LinkOptions::default();
+        store.claim("abc", "https://a.com", &opts).await.unwrap();
+
+        store.visit("abc").await.unwrap();
+        store.visit("abc").await.unwrap();
+        assert_eq!(store.stats.hits(), 0);
+        assert_eq!(store.stats.misses(), 2);
+    }
+}
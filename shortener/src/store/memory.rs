@@ -0,0 +1,392 @@
+use super::{LinkOptions, LinkPatch, PoolStats, Reuse, UrlRecord, UrlStore};
+use crate::analytics::{Click, DailyClicks, LinkStats, ReferrerClicks, TOP_REFERRERS};
+use crate::auth::ApiKey;
+use crate::error::Error;
+use async_trait::async_trait;
+use chrono::Utc;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+/// MemoryStore keeps everything in process, it is meant for tests
+/// and local runs where no database is around
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    // id -> record
+    urls: DashMap<String, UrlRecord>,
+    // url -> id, mirrors the UNIQUE constraint on url
+    ids: DashMap<String, String>,
+    // id -> recorded clicks
+    events: DashMap<String, Vec<Click>>,
+    // secret hash -> api key
+    keys: DashMap<String, ApiKey>,
+    settings: DashMap<String, String>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decide what to do with the link `id` that already stores a url,
+    /// the caller holds the `ids` entry of that url
+    fn reuse(&self, id: &str, opts: &LinkOptions) -> Result<(), Error> {
+        let mut link = self.urls.get_mut(id).ok_or(Error::UrlNotFound)?;
+        match link.reuse(opts, Utc::now()) {
+            Reuse::Keep => Ok(()),
+            Reuse::Reset => {
+                *link = UrlRecord {
+                    suspicious: link.suspicious,
+                    ..record(id, &link.url, opts)
+                };
+                self.events.remove(id);
+                Ok(())
+            }
+            Reuse::Conflict => Err(Error::UrlConflict(id.to_string())),
+        }
+    }
+}
+
+fn record(id: &str, url: &str, opts: &LinkOptions) -> UrlRecord {
+    UrlRecord {
+        id: id.to_string(),
+        url: url.to_string(),
+        expires_at: opts.expires_at,
+        max_clicks: opts.max_clicks,
+        clicks: 0,
+        disabled: false,
+        owner: opts.owner.clone(),
+        redirect_status: opts.redirect_status,
+        suspicious: false,
+    }
+}
+
+#[async_trait]
+impl UrlStore for MemoryStore {
+    async fn shorten(&self, id: &str, url: &str, opts: &LinkOptions) -> Result<String, Error> {
+        // never hold a `urls` guard while locking `ids`, `delete` goes the other way
+        match self.ids.entry(url.to_string()) {
+            Entry::Occupied(e) => {
+                self.reuse(e.get(), opts)?;
+                Ok(e.get().clone())
+            }
+            Entry::Vacant(e) => match self.urls.entry(id.to_string()) {
+                Entry::Occupied(_) => Err(Error::IdConflict),
+                Entry::Vacant(v) => {
+                    v.insert(record(id, url, opts));
+                    e.insert(id.to_string());
+                    Ok(id.to_string())
+                }
+            },
+        }
+    }
+
+    async fn claim(&self, id: &str, url: &str, opts: &LinkOptions) -> Result<(), Error> {
+        match self.ids.entry(url.to_string()) {
+            Entry::Occupied(e) if e.get() == id => self.reuse(id, opts),
+            Entry::Occupied(e) => Err(Error::UrlConflict(e.get().clone())),
+            Entry::Vacant(e) => match self.urls.entry(id.to_string()) {
+                Entry::Occupied(_) => Err(Error::AliasConflict(id.to_string())),
+                Entry::Vacant(v) => {
+                    v.insert(record(id, url, opts));
+                    e.insert(id.to_string());
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    async fn resolve(&self, id: &str) -> Result<UrlRecord, Error> {
+        self.urls
+            .get(id)
+            .map(|v| v.value().clone())
+            .ok_or(Error::UrlNotFound)
+    }
+
+    async fn visit(&self, id: &str) -> Result<UrlRecord, Error> {
+        let mut record = self.urls.get_mut(id).ok_or(Error::UrlNotFound)?;
+        if record.disabled {
+            return Err(Error::UrlDisabled);
+        }
+        if record.is_expired(Utc::now()) {
+            return Err(Error::UrlExpired);
+        }
+        record.clicks += 1;
+        Ok(record.clone())
+    }
+
+    async fn record_clicks(&self, clicks: &[Click]) -> Result<(), Error> {
+        for click in clicks.iter().filter(|c| self.urls.contains_key(&c.id)) {
+            self.events
+                .entry(click.id.clone())
+                .or_default()
+                .push(click.clone());
+        }
+        Ok(())
+    }
+
+    async fn add_clicks(&self, id: &str, n: i64) -> Result<(), Error> {
+        let mut record = self.urls.get_mut(id).ok_or(Error::UrlNotFound)?;
+        record.clicks += n;
+        Ok(())
+    }
+
+    async fn stats(&self, id: &str) -> Result<LinkStats, Error> {
+        self.resolve(id).await?;
+
+        let mut daily: BTreeMap<String, i64> = BTreeMap::new();
+        let mut referrers: BTreeMap<String, i64> = BTreeMap::new();
+        if let Some(events) = self.events.get(id) {
+            for click in events.iter() {
+                let day = click.clicked_at.format("%Y-%m-%d").to_string();
+                *daily.entry(day).or_default() += 1;
+                if let Some(ref referrer) = click.referrer {
+                    *referrers.entry(referrer.clone()).or_default() += 1;
+                }
+            }
+        }
+
+        let daily: Vec<DailyClicks> = daily
+            .into_iter()
+            .map(|(day, clicks)| DailyClicks { day, clicks })
+            .collect();
+        let mut top_referrers: Vec<ReferrerClicks> = referrers
+            .into_iter()
+            .map(|(referrer, clicks)| ReferrerClicks { referrer, clicks })
+            .collect();
+        // stable sort keeps referrers with equal counts in name order
+        top_referrers.sort_by_key(|r| Reverse(r.clicks));
+        top_referrers.truncate(TOP_REFERRERS as usize);
+
+        Ok(LinkStats {
+            total: daily.iter().map(|d| d.clicks).sum(),
+            daily,
+            top_referrers,
+        })
+    }
+
+    async fn purge_expired(&self) -> Result<u64, Error> {
+        let now = Utc::now();
+        let mut expired = Vec::new();
+        self.urls.retain(|_, v| {
+            if v.is_expired(now) {
+                expired.push((v.id.clone(), v.url.clone()));
+                return false;
+            }
+            true
+        });
+        for (id, url) in expired.iter() {
+            self.ids.remove(url);
+            self.events.remove(id);
+        }
+        Ok(expired.len() as u64)
+    }
+
+    async fn update(&self, id: &str, patch: &LinkPatch) -> Result<UrlRecord, Error> {
+        if let Some(ref url) = patch.url {
+            // same lock order as `shorten`, `ids` first
+            let old = match self.ids.entry(url.clone()) {
+                Entry::Occupied(e) if e.get() != id => {
+                    return Err(Error::UrlConflict(e.get().clone()))
+                }
+                Entry::Occupied(_) => None,
+                Entry::Vacant(e) => {
+                    let mut record = self.urls.get_mut(id).ok_or(Error::UrlNotFound)?;
+                    let old = std::mem::replace(&mut record.url, url.clone());
+                    e.insert(id.to_string());
+                    Some(old)
+                }
+            };
+            if let Some(old) = old {
+                self.ids.remove(&old);
+            }
+        }
+
+        let mut record = self.urls.get_mut(id).ok_or(Error::UrlNotFound)?;
+        patch.apply(&mut record);
+        Ok(record.clone())
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), Error> {
+        let (_, record) = self.urls.remove(id).ok_or(Error::UrlNotFound)?;
+        self.ids.remove(&record.url);
+        self.events.remove(id);
+        Ok(())
+    }
+
+    async fn list(&self, after: Option<&str>, limit: usize) -> Result<Vec<UrlRecord>, Error> {
+        let mut records: Vec<UrlRecord> = self
+            .urls
+            .iter()
+            .filter(|e| after.is_none_or(|after| e.key().as_str() > after))
+            .map(|e| e.value().clone())
+            .collect();
+        records.sort_by(|a, b| a.id.cmp(&b.id));
+        records.truncate(limit);
+        Ok(records)
+    }
+
+    async fn max_id(&self, length: usize) -> Result<Option<String>, Error> {
+        Ok(self
+            .urls
+            .iter()
+            .map(|e| e.key().clone())
+            .filter(|id| id.len() == length && id.chars().all(|c| c.is_ascii_alphanumeric()))
+            .max())
+    }
+
+    async fn create_api_key(&self, key: &ApiKey, hash: &str) -> Result<(), Error> {
+        self.keys.insert(hash.to_string(), key.clone());
+        Ok(())
+    }
+
+    async fn find_api_key(&self, hash: &str) -> Result<ApiKey, Error> {
+        self.keys
+            .get(hash)
+            .map(|v| v.value().clone())
+            .ok_or(Error::Unauthorized)
+    }
+
+    async fn setting(&self, name: &str, value: &str) -> Result<String, Error> {
+        Ok(self
+            .settings
+            .entry(name.to_string())
+            .or_insert_with(|| value.to_string())
+            .clone())
+    }
+
+    async fn migrate(&self) -> Result<Vec<i64>, Error> {
+        Ok(Vec::new())
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[tokio::test]
+    async fn shorten_same_url_should_return_same_id() {
+        let store = MemoryStore::new();
+        let opts = LinkOptions::default();
+        let id = store
+            .shorten("abc", "https://example.com", &opts)
+            .await
+            .unwrap();
+        assert_eq!(id, "abc");
+
+        let id = store
+            .shorten("def", "https://example.com", &opts)
+            .await
+            .unwrap();
+        assert_eq!(id, "abc");
+
+        let ret = store.shorten("abc", "https://example.org", &opts).await;
+        assert!(matches!(ret, Err(Error::IdConflict)));
+
+        store
+            .claim("abc", "https://example.com", &opts)
+            .await
+            .unwrap();
+        let ret = store.claim("abc", "https://example.org", &opts).await;
+        assert!(matches!(ret, Err(Error::AliasConflict(_))));
+        let ret = store.claim("xyz", "https://example.com", &opts).await;
+        assert!(matches!(ret, Err(Error::UrlConflict(ref id)) if id == "abc"));
+
+        store.delete("abc").await.unwrap();
+        assert!(matches!(
+            store.resolve("abc").await,
+            Err(Error::UrlNotFound)
+        ));
+        assert_eq!(store.list(None, 10).await.unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn shorten_should_reuse_live_links() {
+        crate::store::suite::shorten_should_reuse_live_links(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn removed_links_should_lose_their_clicks() {
+        let store = MemoryStore::new();
+        crate::store::suite::removed_links_should_lose_their_clicks(&store).await;
+        crate::store::suite::settings_should_keep_their_first_value(&store).await;
+    }
+
+    #[tokio::test]
+    async fn list_should_page_by_id() {
+        crate::store::suite::list_should_page_by_id(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn expired_links_should_be_gone() {
+        let store = MemoryStore::new();
+        let opts = LinkOptions {
+            max_clicks: Some(2),
+            ..Default::default()
+        };
+        store.claim("twice", "https://a.com", &opts).await.unwrap();
+        let opts = LinkOptions {
+            expires_at: Some(Utc::now() - Duration::seconds(1)),
+            ..Default::default()
+        };
+        store.claim("past", "https://b.com", &opts).await.unwrap();
+
+        assert!(store.visit("twice").await.is_ok());
+        assert!(store.visit("twice").await.is_ok());
+        assert!(matches!(store.visit("twice").await, Err(Error::UrlExpired)));
+        assert!(matches!(store.visit("past").await, Err(Error::UrlExpired)));
+
+        assert_eq!(store.purge_expired().await.unwrap(), 2);
+        assert!(store.list(None, 10).await.unwrap().is_empty());
+        // the urls can be shortened again
+        store
+            .claim("past", "https://b.com", &LinkOptions::default())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn update_should_patch_links() {
+        let store = MemoryStore::new();
+        let opts = LinkOptions {
+            max_clicks: Some(1),
+            ..Default::default()
+        };
+        store.claim("abc", "https://a.com", &opts).await.unwrap();
+        store.claim("def", "https://b.com", &opts).await.unwrap();
+
+        let patch = LinkPatch {
+            url: Some("https://b.com".to_string()),
+            ..Default::default()
+        };
+        let ret = store.update("abc", &patch).await;
+        assert!(matches!(ret, Err(Error::UrlConflict(ref id)) if id == "def"));
+
+        let patch = LinkPatch {
+            url: Some("https://c.com".to_string()),
+            disabled: Some(true),
+            max_clicks: Some(None),
+            ..Default::default()
+        };
+        let record = store.update("abc", &patch).await.unwrap();
+        assert_eq!(record.url, "https://c.com");
+        assert_eq!(record.max_clicks, None);
+        assert!(matches!(store.visit("abc").await, Err(Error::UrlDisabled)));
+        // the old url is free again
+        let id = store.shorten("xyz", "https://a.com", &opts).await.unwrap();
+        assert_eq!(id, "xyz");
+        // disabled links are not handed out again
+        let ret = store.shorten("uvw", "https://c.com", &opts).await;
+        assert!(matches!(ret, Err(Error::UrlConflict(ref id)) if id == "abc"));
+    }
+}
@@ -0,0 +1,98 @@
+use crate::error::Error;
+use sha2::{Digest, Sha256};
+
+/// A schema change, applied once and in order of `version`
+#[derive(Debug)]
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Embed `migrations/<backend>/<file>.sql`
+macro_rules! migration {
+    ($version:literal, $backend:literal, $file:literal) => {
+        Migration {
+            version: $version,
+            name: $file,
+            sql: include_str!(concat!("../../migrations/", $backend, "/", $file, ".sql")),
+        }
+    };
+}
+
+pub const POSTGRES: &[Migration] = &[
+    migration!(1, "postgres", "0001_create_urls"),
+    migration!(2, "postgres", "0002_link_limits"),
+    migration!(3, "postgres", "0003_clicks"),
+    migration!(4, "postgres", "0004_api_keys"),
+    migration!(5, "postgres", "0005_redirect_status"),
+    migration!(6, "postgres", "0006_widen_ids"),
+    migration!(7, "postgres", "0007_settings"),
+];
+
+pub const SQLITE: &[Migration] = &[
+    migration!(1, "sqlite", "0001_initial"),
+    migration!(2, "sqlite", "0002_settings"),
+];
+
+impl Migration {
+    /// Recorded with the migration, so edits to applied files are caught
+    pub fn checksum(&self) -> String {
+        Sha256::digest(self.sql.as_bytes())
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+}
+
+/// Compare `applied` (version, checksum) rows against `migrations` and
+/// return the ones still to run
+pub fn pending<'a>(
+    migrations: &'a [Migration],
+    applied: &[(i64, String)],
+) -> Result<Vec<&'a Migration>, Error> {
+    for (version, checksum) in applied {
+        match migrations.iter().find(|m| m.version == *version) {
+            Some(m) if m.checksum() == *checksum => {}
+            Some(_) => return Err(Error::MigrationChanged(*version)),
+            None => return Err(Error::UnknownMigration(*version)),
+        }
+    }
+    Ok(migrations
+        .iter()
+        .filter(|m| applied.iter().all(|(v, _)| *v != m.version))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrations_should_be_ordered() {
+        for list in [POSTGRES, SQLITE] {
+            for (i, m) in list.iter().enumerate() {
+                assert_eq!(m.version, i as i64 + 1, "{}", m.name);
+            }
+        }
+    }
+
+    #[test]
+    fn pending_should_check_applied() {
+        let applied = vec![(1, POSTGRES[0].checksum())];
+        let todo = pending(POSTGRES, &applied).unwrap();
+        assert_eq!(todo.len(), POSTGRES.len() - 1);
+        assert_eq!(todo[0].version, 2);
+
+        let changed = vec![(1, "x".to_string())];
+        assert!(matches!(
+            pending(POSTGRES, &changed),
+            Err(Error::MigrationChanged(1))
+        ));
+        let unknown = vec![(99, "x".to_string())];
+        assert!(matches!(
+            pending(POSTGRES, &unknown),
+            Err(Error::UnknownMigration(99))
+        ));
+    }
+}
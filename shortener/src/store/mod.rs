@@ -0,0 +1,393 @@
+use crate::analytics::{Click, LinkStats};
+use crate::auth::ApiKey;
+use crate::config::StoreConfig;
+use crate::error::Error;
+use async_trait::async_trait;
+use chrono::{DateTime, DurationRound, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+mod cache;
+mod memory;
+mod migrations;
+mod postgres;
+mod sqlite;
+
+pub use cache::CachedStore;
+pub use memory::MemoryStore;
+pub use postgres::PgStore;
+pub use sqlite::SqliteStore;
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct UrlRecord {
+    #[sqlx(default)]
+    pub id: String,
+    #[sqlx(default)]
+    pub url: String,
+    #[sqlx(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[sqlx(default)]
+    pub max_clicks: Option<i64>,
+    #[sqlx(default)]
+    pub clicks: i64,
+    #[sqlx(default)]
+    pub disabled: bool,
+    /// Id of the api key that created the link
+    #[sqlx(default)]
+    pub owner: Option<String>,
+    /// Redirect status, the server default if unset
+    #[sqlx(default)]
+    pub redirect_status: Option<i32>,
+    /// Visitors get a preview page instead of a redirect
+    #[sqlx(default)]
+    pub suspicious: bool,
+}
+
+impl UrlRecord {
+    /// Field names in serialization order
+    pub const COLUMNS: [&'static str; 9] = [
+        "id",
+        "url",
+        "expires_at",
+        "max_clicks",
+        "clicks",
+        "disabled",
+        "owner",
+        "redirect_status",
+        "suspicious",
+    ];
+
+    /// Whether the link hit its expiry time or click budget
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|t| t <= now)
+            || self.max_clicks.is_some_and(|max| self.clicks >= max)
+    }
+
+    /// What shortening the url of this link again with `opts` should do
+    pub fn reuse(&self, opts: &LinkOptions, now: DateTime<Utc>) -> Reuse {
+        // databases keep microseconds
+        let micros = |t: DateTime<Utc>| {
+            t.duration_trunc(chrono::Duration::microseconds(1))
+                .unwrap_or(t)
+        };
+        if self.disabled {
+            Reuse::Conflict
+        } else if self.is_expired(now) {
+            Reuse::Reset
+        } else if self.expires_at.map(micros) == opts.expires_at.map(micros)
+            && self.max_clicks == opts.max_clicks
+            && self.redirect_status == opts.redirect_status
+        {
+            Reuse::Keep
+        } else {
+            Reuse::Conflict
+        }
+    }
+}
+
+/// Outcome of shortening a url that is already stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reuse {
+    /// The link is live with the same limits, hand out its id
+    Keep,
+    /// The link is past its limits, start it over with the new ones
+    /// and drop its click history
+    Reset,
+    /// The link is disabled or has other limits, `Error::UrlConflict`
+    Conflict,
+}
+
+/// Limits applied to a newly created link
+#[derive(Debug, Clone, Default)]
+pub struct LinkOptions {
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_clicks: Option<i64>,
+    pub owner: Option<String>,
+    pub redirect_status: Option<i32>,
+}
+
+/// Changes to an existing link, `None` leaves a field untouched
+/// while `Some(None)` clears a limit
+#[derive(Debug, Clone, Default)]
+pub struct LinkPatch {
+    pub url: Option<String>,
+    pub disabled: Option<bool>,
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    pub max_clicks: Option<Option<i64>>,
+    pub redirect_status: Option<Option<i32>>,
+    pub suspicious: Option<bool>,
+}
+
+impl LinkPatch {
+    pub fn is_empty(&self) -> bool {
+        self.url.is_none()
+            && self.disabled.is_none()
+            && self.expires_at.is_none()
+            && self.max_clicks.is_none()
+            && self.redirect_status.is_none()
+            && self.suspicious.is_none()
+    }
+
+    pub fn apply(&self, record: &mut UrlRecord) {
+        if let Some(ref url) = self.url {
+            record.url = url.clone();
+        }
+        if let Some(disabled) = self.disabled {
+            record.disabled = disabled;
+        }
+        if let Some(expires_at) = self.expires_at {
+            record.expires_at = expires_at;
+        }
+        if let Some(max_clicks) = self.max_clicks {
+            record.max_clicks = max_clicks;
+        }
+        if let Some(redirect_status) = self.redirect_status {
+            record.redirect_status = redirect_status;
+        }
+        if let Some(suspicious) = self.suspicious {
+            record.suspicious = suspicious;
+        }
+    }
+}
+
+/// Connection counts of a database pool
+#[derive(Debug, Clone, Copy)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: u32,
+    pub max: u32,
+}
+
+/// UrlStore persists the mapping between short ids and urls
+#[async_trait]
+pub trait UrlStore: Send + Sync + 'static {
+    /// Store `url` under `id`, returns the id the url is stored under.
+    /// If the url was already shortened its link is reused as `UrlRecord::reuse`
+    /// decides, otherwise `Error::UrlConflict` carries the existing id.
+    /// Returns `Error::IdConflict` if `id` is taken by another url
+    async fn shorten(&self, id: &str, url: &str, opts: &LinkOptions) -> Result<String, Error>;
+    /// Store `url` under exactly `id`, succeeds if that pair already exists
+    /// and can be reused as in `shorten`.
+    /// Returns `Error::UrlConflict` with the existing id if `url` is stored
+    /// under another id, and `Error::AliasConflict` if `id` is taken
+    async fn claim(&self, id: &str, url: &str, opts: &LinkOptions) -> Result<(), Error>;
+    /// Look up the record stored under `id`
+    async fn resolve(&self, id: &str) -> Result<UrlRecord, Error>;
+    /// Count a click on `id` and return its record.
+    /// Returns `Error::UrlExpired` once the link is past its limits
+    /// and `Error::UrlDisabled` if it was switched off
+    async fn visit(&self, id: &str) -> Result<UrlRecord, Error>;
+    /// Persist a batch of clicks, clicks of links that are gone by now are
+    /// dropped
+    async fn record_clicks(&self, clicks: &[Click]) -> Result<(), Error>;
+    /// Advance the click counter of `id` by `n` for visits that were
+    /// served without asking the store, limits are not checked
+    async fn add_clicks(&self, id: &str, n: i64) -> Result<(), Error>;
+    /// Aggregate the recorded clicks of `id`
+    async fn stats(&self, id: &str) -> Result<LinkStats, Error>;
+    /// Remove links that are past their limits and their recorded clicks,
+    /// returns how many links were removed
+    async fn purge_expired(&self) -> Result<u64, Error>;
+    /// Apply `patch` to the link stored under `id` and return the result.
+    /// Returns `Error::UrlConflict` with the existing id if the new url is
+    /// stored under another id
+    async fn update(&self, id: &str, patch: &LinkPatch) -> Result<UrlRecord, Error>;
+    /// Remove the url stored under `id` along with its recorded clicks
+    async fn delete(&self, id: &str) -> Result<(), Error>;
+    /// List up to `limit` records ordered by id, starting after the id `after`
+    async fn list(&self, after: Option<&str>, limit: usize) -> Result<Vec<UrlRecord>, Error>;
+    /// Highest id made of exactly `length` ascii alphanumerics, compared
+    /// bytewise, so sequential ids can resume past it
+    async fn max_id(&self, length: usize) -> Result<Option<String>, Error>;
+    /// Store an api key under the hash of its secret
+    async fn create_api_key(&self, key: &ApiKey, hash: &str) -> Result<(), Error>;
+    /// Find the api key whose secret hashes to `hash`.
+    /// Returns `Error::Unauthorized` if there is none
+    async fn find_api_key(&self, hash: &str) -> Result<ApiKey, Error>;
+    /// Value of the setting `name`, which is set to `value` if it has none yet
+    async fn setting(&self, name: &str, value: &str) -> Result<String, Error>;
+    /// Apply pending schema migrations, returns the versions applied
+    async fn migrate(&self) -> Result<Vec<i64>, Error>;
+    /// Check that the backend is reachable
+    async fn ping(&self) -> Result<(), Error>;
+    /// Connection pool usage, `None` for stores without a pool
+    fn pool_stats(&self) -> Option<PoolStats>;
+}
+
+/// Build a store from a dsn, the backend is picked by its scheme:
+/// `postgres://`, `sqlite:` or `memory://`. Pending migrations are
+/// applied if `auto_migrate` is set
+pub async fn connect(config: &StoreConfig) -> Result<Arc<dyn UrlStore>, Error> {
+    let dsn = config.dsn.as_str();
+    let store: Arc<dyn UrlStore> =
+        if dsn.starts_with("postgres://") || dsn.starts_with("postgresql://") {
+            Arc::new(PgStore::try_new(config).await?)
+        } else if dsn.starts_with("sqlite:") {
+            Arc::new(SqliteStore::try_new(config).await?)
+        } else if dsn.starts_with("memory://") {
+            Arc::new(MemoryStore::new())
+        } else {
+            return Err(Error::UnsupportedStore(dsn.to_string()));
+        };
+    if config.auto_migrate {
+        for version in store.migrate().await? {
+            info!("applied migration {version}");
+        }
+    }
+    Ok(store)
+}
+
+/// Periodically remove expired links in the background
+pub fn spawn_purger(store: Arc<dyn UrlStore>, every: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            match store.purge_expired().await {
+                Ok(0) => {}
+                Ok(n) => info!("purged {n} expired links"),
+                Err(e) => warn!("failed to purge expired links: {e}"),
+            }
+        }
+    });
+}
+
+/// Checks every store has to pass, run from the tests of each backend
+#[cfg(test)]
+pub(crate) mod suite {
+    use super::*;
+
+    fn click(id: &str) -> Click {
+        Click {
+            id: id.to_string(),
+            clicked_at: Utc::now(),
+            referrer: None,
+            user_agent: None,
+            ip_hash: None,
+        }
+    }
+
+    /// Shortening a stored url again only hands out its id if the link is
+    /// live with the same limits, dead links start over
+    pub async fn shorten_should_reuse_live_links(store: &dyn UrlStore) {
+        let opts = LinkOptions::default();
+        let capped = LinkOptions {
+            max_clicks: Some(1),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            store.shorten("a1", "https://a.com", &opts).await.unwrap(),
+            "a1"
+        );
+        assert_eq!(
+            store.shorten("b1", "https://a.com", &opts).await.unwrap(),
+            "a1"
+        );
+        let ret = store.shorten("b1", "https://a.com", &capped).await;
+        assert!(matches!(ret, Err(Error::UrlConflict(ref id)) if id == "a1"));
+        let ret = store.claim("a1", "https://a.com", &capped).await;
+        assert!(matches!(ret, Err(Error::UrlConflict(ref id)) if id == "a1"));
+
+        // used up links are reset with the new limits and lose their stats
+        assert_eq!(
+            store.shorten("c1", "https://c.com", &capped).await.unwrap(),
+            "c1"
+        );
+        store.visit("c1").await.unwrap();
+        store.record_clicks(&[click("c1")]).await.unwrap();
+        assert!(matches!(store.visit("c1").await, Err(Error::UrlExpired)));
+        let twice = LinkOptions {
+            max_clicks: Some(2),
+            owner: Some("k2".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            store.shorten("d1", "https://c.com", &twice).await.unwrap(),
+            "c1"
+        );
+        let record = store.resolve("c1").await.unwrap();
+        assert_eq!((record.clicks, record.max_clicks), (0, Some(2)));
+        assert_eq!(record.owner.as_deref(), Some("k2"));
+        assert_eq!(store.stats("c1").await.unwrap().total, 0);
+        assert!(store.visit("c1").await.is_ok());
+
+        let past = LinkOptions {
+            expires_at: Some(Utc::now() - chrono::Duration::seconds(1)),
+            ..Default::default()
+        };
+        store.claim("old", "https://e.com", &past).await.unwrap();
+        store.claim("old", "https://e.com", &opts).await.unwrap();
+        assert!(store.visit("old").await.is_ok());
+
+        // expiry times keep matching after the database rounded them
+        let later = LinkOptions {
+            expires_at: Some(Utc::now() + chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        assert_eq!(
+            store.shorten("f1", "https://f.com", &later).await.unwrap(),
+            "f1"
+        );
+        assert_eq!(
+            store.shorten("g1", "https://f.com", &later).await.unwrap(),
+            "f1"
+        );
+
+        let disable = LinkPatch {
+            disabled: Some(true),
+            ..Default::default()
+        };
+        store.update("a1", &disable).await.unwrap();
+        let ret = store.shorten("h1", "https://a.com", &opts).await;
+        assert!(matches!(ret, Err(Error::UrlConflict(ref id)) if id == "a1"));
+    }
+
+    /// Removed links take their clicks along, so a reclaimed id starts from zero
+    pub async fn removed_links_should_lose_their_clicks(store: &dyn UrlStore) {
+        let opts = LinkOptions::default();
+        store.claim("gone", "https://a.com", &opts).await.unwrap();
+        store.record_clicks(&[click("gone")]).await.unwrap();
+        assert_eq!(store.stats("gone").await.unwrap().total, 1);
+        store.delete("gone").await.unwrap();
+        // a batch that was still buffered when the link went away
+        store.record_clicks(&[click("gone")]).await.unwrap();
+        store.claim("gone", "https://b.com", &opts).await.unwrap();
+        assert_eq!(store.stats("gone").await.unwrap().total, 0);
+
+        let past = LinkOptions {
+            expires_at: Some(Utc::now() - chrono::Duration::seconds(1)),
+            ..Default::default()
+        };
+        store.claim("old", "https://c.com", &past).await.unwrap();
+        store.record_clicks(&[click("old")]).await.unwrap();
+        assert_eq!(store.purge_expired().await.unwrap(), 1);
+        store.record_clicks(&[click("old")]).await.unwrap();
+        store.claim("old", "https://d.com", &opts).await.unwrap();
+        assert_eq!(store.stats("old").await.unwrap().total, 0);
+    }
+
+    pub async fn settings_should_keep_their_first_value(store: &dyn UrlStore) {
+        assert_eq!(store.setting("salt", "a").await.unwrap(), "a");
+        assert_eq!(store.setting("salt", "b").await.unwrap(), "a");
+        assert_eq!(store.setting("other", "b").await.unwrap(), "b");
+    }
+
+    /// Pages continue after the last id seen, whatever happened in between
+    pub async fn list_should_page_by_id(store: &dyn UrlStore) {
+        let opts = LinkOptions::default();
+        for id in ["p3", "p1", "p4", "p2"] {
+            let url = format!("https://{id}.com");
+            store.claim(id, &url, &opts).await.unwrap();
+        }
+        let ids = |records: Vec<UrlRecord>| records.into_iter().map(|r| r.id).collect::<Vec<_>>();
+
+        assert_eq!(ids(store.list(None, 2).await.unwrap()), ["p1", "p2"]);
+        store.delete("p1").await.unwrap();
+        assert_eq!(ids(store.list(Some("p2"), 2).await.unwrap()), ["p3", "p4"]);
+        assert!(store.list(Some("p4"), 2).await.unwrap().is_empty());
+    }
+}
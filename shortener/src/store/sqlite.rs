@@ -0,0 +1,610 @@
+use super::migrations::{self, SQLITE};
+use super::{LinkOptions, LinkPatch, PoolStats, Reuse, UrlRecord, UrlStore};
+use crate::analytics::{Click, DailyClicks, LinkStats, ReferrerClicks, TOP_REFERRERS};
+use crate::auth::ApiKey;
+use crate::config::StoreConfig;
+use crate::error::Error;
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::Error::Database;
+use sqlx::QueryBuilder;
+use sqlx::{Executor, SqlitePool};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    db: SqlitePool,
+}
+
+impl SqliteStore {
+    pub async fn try_new(config: &StoreConfig) -> Result<Self, Error> {
+        let url = config.dsn.as_str();
+        let opts = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let mut pool = SqlitePoolOptions::new()
+            .max_connections(config.max_connections)
+            .acquire_timeout(Duration::from_secs(config.acquire_timeout_secs));
+        if url.contains(":memory:") {
+            // every connection to an in-memory database gets its own copy,
+            // so keep exactly one connection alive for the pool's lifetime
+            pool = pool
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None);
+        }
+        let pool = pool.connect_with(opts).await?;
+        Ok(Self { db: pool })
+    }
+
+    /// Reuse or reset the link already storing `url` as `UrlRecord::reuse` decides,
+    /// returns its id or `None` if there is no such link
+    async fn reuse(&self, url: &str, opts: &LinkOptions) -> Result<Option<String>, Error> {
+        // sqlite has no row locks, writers are serialized by the database lock
+        let mut tx = self.db.begin().await?;
+        let record: Option<UrlRecord> = sqlx::query_as(
+            "SELECT id, url, expires_at, max_clicks, clicks, disabled, owner, redirect_status, suspicious FROM urls WHERE url = ?",
+        )
+        .bind(url)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(record) = record else {
+            return Ok(None);
+        };
+
+        match record.reuse(opts, Utc::now()) {
+            Reuse::Keep => {}
+            Reuse::Reset => {
+                sqlx::query(
+                    "UPDATE urls SET expires_at = ?, max_clicks = ?, owner = ?, redirect_status = ?, clicks = 0 WHERE id = ?",
+                )
+                .bind(opts.expires_at)
+                .bind(opts.max_clicks)
+                .bind(&opts.owner)
+                .bind(opts.redirect_status)
+                .bind(&record.id)
+                .execute(&mut *tx)
+                .await?;
+                sqlx::query("DELETE FROM clicks WHERE id = ?")
+                    .bind(&record.id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            Reuse::Conflict => return Err(Error::UrlConflict(record.id)),
+        }
+        tx.commit().await?;
+        Ok(Some(record.id))
+    }
+
+    /// Id the exact `url` is stored under
+    async fn find_id(&self, url: &str) -> Result<Option<String>, Error> {
+        let ret: Option<(String,)> = sqlx::query_as("SELECT id FROM urls WHERE url = ?")
+            .bind(url)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(ret.map(|(id,)| id))
+    }
+}
+
+#[async_trait]
+impl UrlStore for SqliteStore {
+    async fn shorten(&self, id: &str, url: &str, opts: &LinkOptions) -> Result<String, Error> {
+        loop {
+            let ret: Result<Option<(String,)>, _> = sqlx::query_as(
+                "INSERT INTO urls (id, url, expires_at, max_clicks, owner, redirect_status) VALUES (?, ?, ?, ?, ?, ?) ON CONFLICT(url) DO NOTHING RETURNING id",
+            )
+            .bind(id)
+            .bind(url)
+            .bind(opts.expires_at)
+            .bind(opts.max_clicks)
+            .bind(&opts.owner)
+            .bind(opts.redirect_status)
+            .fetch_optional(&self.db)
+            .await;
+
+            match ret {
+                Ok(Some(_)) => return Ok(id.to_string()),
+                Ok(None) => {
+                    if let Some(existing) = self.reuse(url, opts).await? {
+                        return Ok(existing);
+                    }
+                    // the link was deleted in the meantime, insert again
+                }
+                Err(Database(ref err)) if err.is_unique_violation() => {
+                    return Err(Error::IdConflict)
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    async fn claim(&self, id: &str, url: &str, opts: &LinkOptions) -> Result<(), Error> {
+        let ret = sqlx::query(
+            "INSERT INTO urls (id, url, expires_at, max_clicks, owner, redirect_status) VALUES (?, ?, ?, ?, ?, ?) ON CONFLICT DO NOTHING",
+        )
+        .bind(id)
+        .bind(url)
+        .bind(opts.expires_at)
+        .bind(opts.max_clicks)
+        .bind(&opts.owner)
+        .bind(opts.redirect_status)
+        .execute(&self.db)
+        .await?;
+        if ret.rows_affected() == 1 {
+            return Ok(());
+        }
+
+        match self.find_id(url).await? {
+            Some(existing) if existing == id => self
+                .reuse(url, opts)
+                .await?
+                .map(|_| ())
+                .ok_or_else(|| Error::AliasConflict(id.to_string())),
+            Some(existing) => Err(Error::UrlConflict(existing)),
+            None => Err(Error::AliasConflict(id.to_string())),
+        }
+    }
+
+    async fn resolve(&self, id: &str) -> Result<UrlRecord, Error> {
+        let ret: Option<UrlRecord> =
+            sqlx::query_as("SELECT id, url, expires_at, max_clicks, clicks, disabled, owner, redirect_status, suspicious FROM urls WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.db)
+                .await?;
+
+        ret.ok_or(Error::UrlNotFound)
+    }
+
+    async fn visit(&self, id: &str) -> Result<UrlRecord, Error> {
+        let ret: Option<UrlRecord> = sqlx::query_as(
+            r#"
+            UPDATE urls SET clicks = clicks + 1
+            WHERE id = ?
+                AND (expires_at IS NULL OR expires_at > ?)
+                AND (max_clicks IS NULL OR clicks < max_clicks)
+                AND NOT disabled
+            RETURNING id, url, expires_at, max_clicks, clicks, disabled, owner, redirect_status, suspicious
+            "#,
+        )
+        .bind(id)
+        .bind(Utc::now())
+        .fetch_optional(&self.db)
+        .await?;
+
+        match ret {
+            Some(record) => Ok(record),
+            // either the link does not exist, is disabled or is past its limits
+            None => match self.resolve(id).await? {
+                record if record.disabled => Err(Error::UrlDisabled),
+                _ => Err(Error::UrlExpired),
+            },
+        }
+    }
+
+    async fn record_clicks(&self, clicks: &[Click]) -> Result<(), Error> {
+        let mut builder = QueryBuilder::new(
+            "INSERT INTO clicks (id, clicked_at, referrer, user_agent, ip_hash) \
+             SELECT v.column1, v.column2, v.column3, v.column4, v.column5 FROM (",
+        );
+        builder.push_values(clicks, |mut b, click| {
+            b.push_bind(&click.id)
+                .push_bind(click.clicked_at)
+                .push_bind(&click.referrer)
+                .push_bind(&click.user_agent)
+                .push_bind(&click.ip_hash);
+        });
+        // skip clicks of links removed since they were buffered
+        builder.push(") AS v JOIN urls ON urls.id = v.column1");
+        builder.build().execute(&self.db).await?;
+        Ok(())
+    }
+
+    async fn add_clicks(&self, id: &str, n: i64) -> Result<(), Error> {
+        let ret = sqlx::query("UPDATE urls SET clicks = clicks + ? WHERE id = ?")
+            .bind(n)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(Error::UrlNotFound);
+        }
+        Ok(())
+    }
+
+    async fn stats(&self, id: &str) -> Result<LinkStats, Error> {
+        self.resolve(id).await?;
+
+        let daily: Vec<DailyClicks> = sqlx::query_as(
+            "SELECT date(clicked_at) AS day, COUNT(*) AS clicks FROM clicks WHERE id = ? GROUP BY day ORDER BY day",
+        )
+        .bind(id)
+        .fetch_all(&self.db)
+        .await?;
+        let top_referrers: Vec<ReferrerClicks> = sqlx::query_as(
+            "SELECT referrer, COUNT(*) AS clicks FROM clicks WHERE id = ? AND referrer IS NOT NULL GROUP BY referrer ORDER BY clicks DESC, referrer LIMIT ?",
+        )
+        .bind(id)
+        .bind(TOP_REFERRERS)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(LinkStats {
+            total: daily.iter().map(|d| d.clicks).sum(),
+            daily,
+            top_referrers,
+        })
+    }
+
+    async fn purge_expired(&self) -> Result<u64, Error> {
+        let expired = "expires_at <= ? OR (max_clicks IS NOT NULL AND clicks >= max_clicks)";
+        let now = Utc::now();
+        let mut tx = self.db.begin().await?;
+        sqlx::query(&format!(
+            "DELETE FROM clicks WHERE id IN (SELECT id FROM urls WHERE {expired})"
+        ))
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        let ret = sqlx::query(&format!("DELETE FROM urls WHERE {expired}"))
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(ret.rows_affected())
+    }
+
+    async fn update(&self, id: &str, patch: &LinkPatch) -> Result<UrlRecord, Error> {
+        if patch.is_empty() {
+            return self.resolve(id).await;
+        }
+
+        let mut builder = QueryBuilder::new("UPDATE urls SET ");
+        let mut fields = builder.separated(", ");
+        if let Some(ref url) = patch.url {
+            fields.push("url = ").push_bind_unseparated(url);
+        }
+        if let Some(disabled) = patch.disabled {
+            fields.push("disabled = ").push_bind_unseparated(disabled);
+        }
+        if let Some(expires_at) = patch.expires_at {
+            fields
+                .push("expires_at = ")
+                .push_bind_unseparated(expires_at);
+        }
+        if let Some(max_clicks) = patch.max_clicks {
+            fields
+                .push("max_clicks = ")
+                .push_bind_unseparated(max_clicks);
+        }
+        if let Some(redirect_status) = patch.redirect_status {
+            fields
+                .push("redirect_status = ")
+                .push_bind_unseparated(redirect_status);
+        }
+        if let Some(suspicious) = patch.suspicious {
+            fields
+                .push("suspicious = ")
+                .push_bind_unseparated(suspicious);
+        }
+        builder
+            .push(" WHERE id = ")
+            .push_bind(id)
+            .push(" RETURNING id, url, expires_at, max_clicks, clicks, disabled, owner, redirect_status, suspicious");
+
+        let ret = builder
+            .build_query_as::<UrlRecord>()
+            .fetch_optional(&self.db)
+            .await;
+        match ret {
+            Ok(record) => record.ok_or(Error::UrlNotFound),
+            Err(Database(err)) if err.is_unique_violation() => {
+                let url = patch.url.as_deref().unwrap_or_default();
+                match self.find_id(url).await? {
+                    Some(existing) => Err(Error::UrlConflict(existing)),
+                    // the other link was deleted in the meantime
+                    None => Err(Database(err).into()),
+                }
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), Error> {
+        let mut tx = self.db.begin().await?;
+        let ret = sqlx::query("DELETE FROM urls WHERE id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(Error::UrlNotFound);
+        }
+
+        sqlx::query("DELETE FROM clicks WHERE id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn list(&self, after: Option<&str>, limit: usize) -> Result<Vec<UrlRecord>, Error> {
+        // ids are never empty, so "" sorts before all of them
+        let ret = sqlx::query_as(
+            "SELECT id, url, expires_at, max_clicks, clicks, disabled, owner, redirect_status, suspicious FROM urls WHERE id > ? ORDER BY id LIMIT ?",
+        )
+        .bind(after.unwrap_or_default())
+        .bind(limit as i64)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(ret)
+    }
+
+    async fn max_id(&self, length: usize) -> Result<Option<String>, Error> {
+        // sqlite compares text bytewise by default
+        let ret: (Option<String>,) = sqlx::query_as(
+            "SELECT max(id) FROM urls WHERE length(id) = ? AND id NOT GLOB '*[^0-9A-Za-z]*'",
+        )
+        .bind(length as i64)
+        .fetch_one(&self.db)
+        .await?;
+        Ok(ret.0)
+    }
+
+    async fn create_api_key(&self, key: &ApiKey, hash: &str) -> Result<(), Error> {
+        sqlx::query("INSERT INTO api_keys (id, name, scope, key_hash) VALUES (?, ?, ?, ?)")
+            .bind(&key.id)
+            .bind(&key.name)
+            .bind(key.scope.as_str())
+            .bind(hash)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn find_api_key(&self, hash: &str) -> Result<ApiKey, Error> {
+        let ret: Option<(String, String, String)> =
+            sqlx::query_as("SELECT id, name, scope FROM api_keys WHERE key_hash = ?")
+                .bind(hash)
+                .fetch_optional(&self.db)
+                .await?;
+
+        let (id, name, scope) = ret.ok_or(Error::Unauthorized)?;
+        Ok(ApiKey {
+            id,
+            name,
+            scope: scope.parse()?,
+        })
+    }
+
+    async fn setting(&self, name: &str, value: &str) -> Result<String, Error> {
+        sqlx::query("INSERT INTO settings (name, value) VALUES (?, ?) ON CONFLICT DO NOTHING")
+            .bind(name)
+            .bind(value)
+            .execute(&self.db)
+            .await?;
+        let ret: (String,) = sqlx::query_as("SELECT value FROM settings WHERE name = ?")
+            .bind(name)
+            .fetch_one(&self.db)
+            .await?;
+        Ok(ret.0)
+    }
+
+    async fn migrate(&self) -> Result<Vec<i64>, Error> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS migrations (
+                version INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                checksum TEXT NOT NULL,
+                applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&self.db)
+        .await?;
+        let applied: Vec<(i64, String)> =
+            sqlx::query_as("SELECT version, checksum FROM migrations ORDER BY version")
+                .fetch_all(&self.db)
+                .await?;
+
+        let mut done = Vec::new();
+        for m in migrations::pending(SQLITE, &applied)? {
+            let mut tx = self.db.begin().await?;
+            // without arguments the script runs unprepared, so it may hold several statements
+            tx.execute(m.sql).await?;
+            sqlx::query("INSERT INTO migrations (version, name, checksum) VALUES (?, ?, ?)")
+                .bind(m.version)
+                .bind(m.name)
+                .bind(m.checksum())
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            done.push(m.version);
+        }
+        Ok(done)
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        sqlx::query("SELECT 1").execute(&self.db).await?;
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(PoolStats {
+            size: self.db.size(),
+            idle: self.db.num_idle() as u32,
+            max: self.db.options().get_max_connections(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{hash_secret, Scope};
+    use chrono::Duration;
+
+    async fn store() -> SqliteStore {
+        let config = StoreConfig {
+            dsn: "sqlite::memory:".to_string(),
+            ..Default::default()
+        };
+        let store = SqliteStore::try_new(&config).await.unwrap();
+        assert_eq!(store.migrate().await.unwrap(), vec![1, 2]);
+        store
+    }
+
+    #[tokio::test]
+    async fn shorten_should_reuse_live_links() {
+        crate::store::suite::shorten_should_reuse_live_links(&store().await).await;
+    }
+
+    #[tokio::test]
+    async fn removed_links_should_lose_their_clicks() {
+        let store = store().await;
+        crate::store::suite::removed_links_should_lose_their_clicks(&store).await;
+        crate::store::suite::settings_should_keep_their_first_value(&store).await;
+    }
+
+    #[tokio::test]
+    async fn list_should_page_by_id() {
+        crate::store::suite::list_should_page_by_id(&store().await).await;
+    }
+
+    #[tokio::test]
+    async fn sqlite_store_should_work() {
+        let store = store().await;
+        assert!(store.migrate().await.unwrap().is_empty());
+        let opts = LinkOptions::default();
+        let id = store
+            .shorten("abc", "https://example.com", &opts)
+            .await
+            .unwrap();
+        assert_eq!(id, "abc");
+
+        let id = store
+            .shorten("def", "https://example.com", &opts)
+            .await
+            .unwrap();
+        assert_eq!(id, "abc");
+
+        let ret = store.shorten("abc", "https://example.org", &opts).await;
+        assert!(matches!(ret, Err(Error::IdConflict)));
+
+        assert_eq!(
+            store.resolve("abc").await.unwrap().url,
+            "https://example.com"
+        );
+
+        store
+            .claim("abc", "https://example.com", &opts)
+            .await
+            .unwrap();
+        store
+            .claim("vanity", "https://example.net", &opts)
+            .await
+            .unwrap();
+        let ret = store.claim("vanity", "https://example.org", &opts).await;
+        assert!(matches!(ret, Err(Error::AliasConflict(_))));
+        let ret = store.claim("other", "https://example.net", &opts).await;
+        assert!(matches!(ret, Err(Error::UrlConflict(ref id)) if id == "vanity"));
+        assert_eq!(store.list(None, 10).await.unwrap().len(), 2);
+
+        store.delete("abc").await.unwrap();
+        assert!(matches!(
+            store.resolve("abc").await,
+            Err(Error::UrlNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn sqlite_store_should_expire_links() {
+        let store = store().await;
+        let opts = LinkOptions {
+            max_clicks: Some(1),
+            ..Default::default()
+        };
+        store.claim("once", "https://a.com", &opts).await.unwrap();
+        let opts = LinkOptions {
+            expires_at: Some(Utc::now() - Duration::seconds(1)),
+            ..Default::default()
+        };
+        store.claim("past", "https://b.com", &opts).await.unwrap();
+
+        assert_eq!(store.visit("once").await.unwrap().url, "https://a.com");
+        assert!(matches!(store.visit("once").await, Err(Error::UrlExpired)));
+        assert!(matches!(store.visit("past").await, Err(Error::UrlExpired)));
+        assert!(matches!(store.visit("nope").await, Err(Error::UrlNotFound)));
+
+        let clicks: Vec<Click> = ["https://r.com", "https://r.com", "https://s.com"]
+            .into_iter()
+            .map(|referrer| Click {
+                id: "once".to_string(),
+                clicked_at: Utc::now(),
+                referrer: Some(referrer.to_string()),
+                user_agent: None,
+                ip_hash: None,
+            })
+            .collect();
+        store.record_clicks(&clicks).await.unwrap();
+        let stats = store.stats("once").await.unwrap();
+        assert_eq!(stats.total, 3);
+        assert_eq!(
+            stats.daily[0].day,
+            Utc::now().format("%Y-%m-%d").to_string()
+        );
+        assert_eq!(stats.top_referrers[0].referrer, "https://r.com");
+        assert_eq!(stats.top_referrers[0].clicks, 2);
+
+        assert_eq!(store.purge_expired().await.unwrap(), 2);
+        assert!(store.list(None, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sqlite_store_should_update_links_and_keys() {
+        let store = store().await;
+        let opts = LinkOptions {
+            owner: Some("k1".to_string()),
+            ..Default::default()
+        };
+        store.claim("abc", "https://a.com", &opts).await.unwrap();
+        store.claim("def", "https://b.com", &opts).await.unwrap();
+
+        let patch = LinkPatch {
+            url: Some("https://b.com".to_string()),
+            ..Default::default()
+        };
+        let ret = store.update("abc", &patch).await;
+        assert!(matches!(ret, Err(Error::UrlConflict(ref id)) if id == "def"));
+        let ret = store.update("nope", &LinkPatch::default()).await;
+        assert!(matches!(ret, Err(Error::UrlNotFound)));
+
+        let patch = LinkPatch {
+            suspicious: Some(true),
+            redirect_status: Some(Some(307)),
+            ..Default::default()
+        };
+        let record = store.update("def", &patch).await.unwrap();
+        assert!(record.suspicious);
+        assert_eq!(record.redirect_status, Some(307));
+
+        let patch = LinkPatch {
+            disabled: Some(true),
+            expires_at: Some(Some(Utc::now() + Duration::days(1))),
+            ..Default::default()
+        };
+        let record = store.update("abc", &patch).await.unwrap();
+        assert!(record.disabled);
+        assert_eq!(record.owner.as_deref(), Some("k1"));
+        assert!(matches!(store.visit("abc").await, Err(Error::UrlDisabled)));
+
+        let (key, secret) = ApiKey::generate("ci", Scope::Write);
+        let hash = hash_secret(&secret);
+        store.create_api_key(&key, &hash).await.unwrap();
+        let found = store.find_api_key(&hash).await.unwrap();
+        assert_eq!(found.id, key.id);
+        assert_eq!(found.scope, Scope::Write);
+        let ret = store.find_api_key(&hash_secret("sk_nope")).await;
+        assert!(matches!(ret, Err(Error::Unauthorized)));
+    }
+}
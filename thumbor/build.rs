@@ -6,6 +6,6 @@ fn main() {
         .compile_protos(&["./abi.proto"], &["."])
         .unwrap();
 
-    Command::new("cargo").args(&["fmt"]).output().unwrap();
+    Command::new("cargo").args(["fmt"]).output().unwrap();
     println!("cargo:rerun-if-changed=abi.proto");
 }
@@ -0,0 +1,333 @@
+use anyhow::Result;
+use bytes::Bytes;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+/// CacheKey is the sha256 of everything that determines the output image
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey(String);
+
+impl CacheKey {
+    /// `engine` is part of it, engines do not produce the same bytes
+    pub fn new(url: &str, spec: &str, format: &str, engine: &str) -> Self {
+        let mut hasher = Sha256::new();
+        // length prefixes keep ("ab", "c") and ("a", "bc") apart
+        for part in [url, spec, format, engine] {
+            hasher.update((part.len() as u64).to_be_bytes());
+            hasher.update(part.as_bytes());
+        }
+        let hex = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Self(hex)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Spread keys evenly over `shards` buckets
+    pub fn shard(&self, shards: usize) -> usize {
+        // the key is hex, its first 8 digits are as random as the rest
+        (u32::from_str_radix(&self.0[..8], 16).unwrap() as usize) % shards
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    memory_hits: AtomicU64,
+    disk_hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub memory_hits: u64,
+    pub disk_hits: u64,
+    pub misses: u64,
+}
+
+/// ResultCache keeps processed images in memory, backed by an optional disk tier
+pub struct ResultCache {
+    memory: Mutex<LruCache<CacheKey, Bytes>>,
+    disk: Option<DiskCache>,
+    counters: Counters,
+}
+
+impl ResultCache {
+    pub fn new(entries: NonZeroUsize, disk: Option<DiskCache>) -> Self {
+        Self {
+            memory: Mutex::new(LruCache::new(entries)),
+            disk,
+            counters: Counters::default(),
+        }
+    }
+
+    pub async fn get(&self, key: &CacheKey) -> Option<Bytes> {
+        let hit = self.memory.lock().unwrap().get(key).cloned();
+        if let Some(data) = hit {
+            self.counters.memory_hits.fetch_add(1, Ordering::Relaxed);
+            return Some(data);
+        }
+
+        if let Some(data) = self.disk_get(key).await {
+            self.counters.disk_hits.fetch_add(1, Ordering::Relaxed);
+            self.memory.lock().unwrap().put(key.clone(), data.clone());
+            return Some(data);
+        }
+
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    pub async fn put(&self, key: CacheKey, data: Bytes) {
+        if let Some(disk) = &self.disk {
+            // a full or read-only disk should not fail the request
+            if let Err(e) = disk.put(&key, &data).await {
+                warn!("failed to write {} to disk cache: {}", key.as_str(), e);
+            }
+        }
+        self.memory.lock().unwrap().put(key, data);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            memory_hits: self.counters.memory_hits.load(Ordering::Relaxed),
+            disk_hits: self.counters.disk_hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    async fn disk_get(&self, key: &CacheKey) -> Option<Bytes> {
+        let disk = self.disk.as_ref()?;
+        match disk.get(key).await {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("failed to read {} from disk cache: {}", key.as_str(), e);
+                None
+            }
+        }
+    }
+}
+
+/// Files on disk and their sizes, least recently used first
+struct DiskIndex {
+    entries: LruCache<CacheKey, u64>,
+    size: u64,
+}
+
+/// DiskCache stores one file per key and evicts the least recently used
+/// files once `max_size` bytes are exceeded
+pub struct DiskCache {
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<DiskIndex>,
+}
+
+impl DiskCache {
+    /// Open `dir`, picking up the files a previous run left behind
+    pub fn open(dir: impl Into<PathBuf>, max_size: u64) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.ends_with(".tmp") {
+                // an unfinished write from a crashed run
+                let _ = std::fs::remove_file(entry.path());
+                continue;
+            }
+            if name.len() != 64 || !name.bytes().all(|b| b.is_ascii_hexdigit()) {
+                continue;
+            }
+            let meta = entry.metadata()?;
+            files.push((meta.modified()?, CacheKey(name), meta.len()));
+        }
+        files.sort_by_key(|(modified, _, _)| *modified);
+
+        let mut index = DiskIndex {
+            entries: LruCache::unbounded(),
+            size: 0,
+        };
+        for (_, key, len) in files {
+            index.size += len;
+            index.entries.put(key, len);
+        }
+        info!(
+            "disk cache {} has {} files, {} bytes",
+            dir.display(),
+            index.entries.len(),
+            index.size
+        );
+
+        let cache = Self {
+            dir,
+            max_size,
+            index: Mutex::new(index),
+        };
+        // max_size may have shrunk since the last run
+        for key in cache.evict() {
+            let _ = std::fs::remove_file(cache.path(&key));
+        }
+        Ok(cache)
+    }
+
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(key.as_str())
+    }
+
+    async fn get(&self, key: &CacheKey) -> Result<Option<Bytes>> {
+        if self.index.lock().unwrap().entries.get(key).is_none() {
+            return Ok(None);
+        }
+        match tokio::fs::read(self.path(key)).await {
+            Ok(data) => Ok(Some(data.into())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.forget(key);
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn put(&self, key: &CacheKey, data: &[u8]) -> Result<()> {
+        let len = data.len() as u64;
+        if len > self.max_size {
+            return Ok(());
+        }
+
+        // write then rename, so readers never see a partial file
+        let tmp = self.dir.join(format!("{}.tmp", key.as_str()));
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, self.path(key)).await?;
+
+        {
+            let mut index = self.index.lock().unwrap();
+            if let Some(old) = index.entries.put(key.clone(), len) {
+                index.size -= old;
+            }
+            index.size += len;
+        }
+        for key in self.evict() {
+            let _ = tokio::fs::remove_file(self.path(&key)).await;
+        }
+        Ok(())
+    }
+
+    /// Drop entries from the index until it fits, returns the keys to delete
+    fn evict(&self) -> Vec<CacheKey> {
+        let mut index = self.index.lock().unwrap();
+        let mut evicted = Vec::new();
+        while index.size > self.max_size {
+            match index.entries.pop_lru() {
+                Some((key, len)) => {
+                    index.size -= len;
+                    evicted.push(key);
+                }
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    fn forget(&self, key: &CacheKey) {
+        let mut index = self.index.lock().unwrap();
+        if let Some(len) = index.entries.pop(key) {
+            index.size -= len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("thumbor-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn cache_key_should_be_stable() {
+        let key = CacheKey::new("https://a/b.png", "spec", "png", "photon");
+        assert_eq!(
+            key,
+            CacheKey::new("https://a/b.png", "spec", "png", "photon")
+        );
+        assert_eq!(key.as_str().len(), 64);
+        assert_ne!(
+            key,
+            CacheKey::new("https://a/b.png", "spec", "jpeg", "photon")
+        );
+        assert_ne!(
+            key,
+            CacheKey::new("https://a/b.png", "spec", "png", "image")
+        );
+        assert_ne!(
+            CacheKey::new("ab", "c", "png", ""),
+            CacheKey::new("a", "bc", "png", "")
+        );
+    }
+
+    #[tokio::test]
+    async fn result_cache_should_count_hits_and_misses() {
+        let cache = ResultCache::new(NonZeroUsize::new(1).unwrap(), None);
+        let (a, b) = (
+            CacheKey::new("a", "", "", ""),
+            CacheKey::new("b", "", "", ""),
+        );
+
+        assert_eq!(cache.get(&a).await, None);
+        cache.put(a.clone(), Bytes::from_static(b"a")).await;
+        assert_eq!(cache.get(&a).await, Some(Bytes::from_static(b"a")));
+        cache.put(b.clone(), Bytes::from_static(b"b")).await;
+        assert_eq!(cache.get(&a).await, None);
+
+        let stats = cache.stats();
+        assert_eq!(
+            (stats.memory_hits, stats.disk_hits, stats.misses),
+            (1, 0, 2)
+        );
+    }
+
+    #[tokio::test]
+    async fn disk_cache_should_survive_restart_and_stay_bounded() {
+        let dir = temp_dir("disk");
+        let (a, b) = (
+            CacheKey::new("a", "", "", ""),
+            CacheKey::new("b", "", "", ""),
+        );
+
+        let cache = ResultCache::new(
+            NonZeroUsize::new(1).unwrap(),
+            Some(DiskCache::open(&dir, 8).unwrap()),
+        );
+        cache.put(a.clone(), Bytes::from_static(b"aaaa")).await;
+        drop(cache);
+
+        let cache = ResultCache::new(
+            NonZeroUsize::new(1).unwrap(),
+            Some(DiskCache::open(&dir, 8).unwrap()),
+        );
+        assert_eq!(cache.get(&a).await, Some(Bytes::from_static(b"aaaa")));
+        assert_eq!(cache.stats().disk_hits, 1);
+
+        // a and b together exceed 8 bytes, so a goes
+        cache.put(b.clone(), Bytes::from_static(b"bbbbbb")).await;
+        assert!(!dir.join(a.as_str()).exists());
+        assert!(dir.join(b.as_str()).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+use super::ops;
+use crate::pb::*;
+use ab_glyph::FontRef;
+use anyhow::Result;
+use image::{imageops, imageops::FilterType, RgbaImage};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, LazyLock};
+use tracing::info;
+
+const FONT: &[u8] = include_bytes!("../../assets/DejaVuSans.ttf");
+const GOPHER: &[u8] = include_bytes!("../../gopher.png");
+
+/// Name of the built-in watermark, also used when a spec names none
+const BUILTIN: &str = "gopher";
+
+/// Longest text a TextOverlay may draw
+const MAX_TEXT: usize = 1000;
+/// Largest font size of a TextOverlay, in pixels
+const MAX_TEXT_SIZE: f32 = 1000.0;
+/// Widest a watermark may be scaled, relative to the image
+const MAX_WATERMARK_SCALE: f32 = 1.0;
+
+/// Assets with only the built-in watermark, for engines nobody configured
+pub static BUILTIN_ASSETS: LazyLock<Arc<Assets>> = LazyLock::new(|| Arc::new(Assets::builtin()));
+
+/// Assets are the watermarks and the font specs can refer to
+pub struct Assets {
+    watermarks: HashMap<String, RgbaImage>,
+    font: FontRef<'static>,
+}
+
+impl Assets {
+    pub fn builtin() -> Self {
+        let gopher = image::load_from_memory(GOPHER).unwrap().into_rgba8();
+        let gopher = imageops::resize(&gopher, 64, 64, FilterType::Nearest);
+        Self {
+            watermarks: HashMap::from([(BUILTIN.to_string(), gopher)]),
+            font: FontRef::try_from_slice(FONT).unwrap(),
+        }
+    }
+
+    /// The built-in assets plus every image in `dir`, named by its file stem
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self> {
+        let mut assets = Self::builtin();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            // skip anything that is not an image, like a README
+            if !path.is_file() || image::ImageFormat::from_path(&path).is_err() {
+                continue;
+            }
+            let img = image::open(&path)?.into_rgba8();
+            info!("loaded watermark {} from {}", name, path.display());
+            assets.watermarks.insert(name.to_string(), img);
+        }
+        Ok(assets)
+    }
+
+    pub fn watermark(&self, name: &str) -> Option<&RgbaImage> {
+        let name = if name.is_empty() { BUILTIN } else { name };
+        self.watermarks.get(name)
+    }
+
+    pub fn font(&self) -> &FontRef<'static> {
+        &self.font
+    }
+
+    /// Reject specs that refer to assets we don't have or draw them out
+    /// of range
+    pub fn check(&self, specs: &[Spec]) -> Result<(), String> {
+        for spec in specs {
+            match spec.data {
+                Some(spec::Data::Watermark(ref v)) if self.watermark(&v.name).is_none() => {
+                    return Err(format!("unknown watermark {}", v.name));
+                }
+                Some(spec::Data::Watermark(ref v))
+                    if !(0.0..=MAX_WATERMARK_SCALE).contains(&v.scale) =>
+                {
+                    return Err(format!(
+                        "watermark scale {} is not between 0 and {}",
+                        v.scale, MAX_WATERMARK_SCALE
+                    ));
+                }
+                Some(spec::Data::Watermark(ref v))
+                    if v.opacity.is_some_and(|o| !(0.0..=1.0).contains(&o)) =>
+                {
+                    return Err(format!(
+                        "watermark opacity {} is not between 0 and 1",
+                        v.opacity.unwrap()
+                    ));
+                }
+                Some(spec::Data::Text(ref v)) if v.text.chars().count() > MAX_TEXT => {
+                    return Err(format!("text is longer than {} characters", MAX_TEXT));
+                }
+                Some(spec::Data::Text(ref v)) if !(0.0..=MAX_TEXT_SIZE).contains(&v.size) => {
+                    return Err(format!(
+                        "text size {} is not between 0 and {}",
+                        v.size, MAX_TEXT_SIZE
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Size of the canvas a watermark or text `spec` is drawn on before it
+    /// goes onto an image of `size`. Only for specs that passed `check`
+    pub fn overlay_size(&self, size: (u32, u32), spec: &Spec) -> Option<(u32, u32)> {
+        match spec.data {
+            Some(spec::Data::Watermark(ref v)) => {
+                let mark = self.watermark(&v.name)?;
+                Some(ops::watermark_size(size, mark.dimensions(), v.scale))
+            }
+            Some(spec::Data::Text(ref v)) if !v.text.is_empty() => {
+                Some(ops::text_size(&self.font, v))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assets_should_load_named_watermarks() {
+        let dir = std::env::temp_dir().join(format!("thumbor-assets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        RgbaImage::new(8, 4).save(dir.join("acme.png")).unwrap();
+        std::fs::write(dir.join("README"), "not a watermark").unwrap();
+
+        let assets = Assets::load(&dir).unwrap();
+        assert_eq!(assets.watermark("acme").unwrap().dimensions(), (8, 4));
+        assert_eq!(assets.watermark("").unwrap().dimensions(), (64, 64));
+        assert!(assets.watermark("missing").is_none());
+
+        let named = |name: &str, scale, opacity| {
+            Spec::new_named_watermark(name, watermark::Anchor::TopLeft, 0, scale, opacity)
+        };
+        assert!(assets
+            .check(&[named("acme", 0.0, 0.0), Spec::new_watermark(0, 0)])
+            .is_ok());
+        assert!(assets.check(&[named("missing", 0.0, 0.0)]).is_err());
+
+        let long = "x".repeat(MAX_TEXT + 1);
+        let text = Spec::new_text(&long, 0.0, 0, watermark::Anchor::TopLeft, 0);
+        assert!(assets.check(&[text]).is_err());
+
+        let bad = [
+            named("acme", 1.5, 0.0),
+            named("acme", f32::NAN, 0.0),
+            named("acme", 0.0, 2.0),
+            Spec::new_text("x", f32::INFINITY, 0, watermark::Anchor::TopLeft, 0),
+            Spec::new_text("x", -1.0, 0, watermark::Anchor::TopLeft, 0),
+            Spec::new_text("x", 1e5, 0, watermark::Anchor::TopLeft, 0),
+        ];
+        for spec in bad {
+            assert!(
+                assets.check(std::slice::from_ref(&spec)).is_err(),
+                "{:?}",
+                spec
+            );
+        }
+
+        assert_eq!(
+            assets.overlay_size((100, 100), &named("acme", 0.5, 1.0)),
+            Some((50, 25))
+        );
+        let text = Spec::new_text("Hello", 100.0, 0, watermark::Anchor::TopLeft, 0);
+        let (width, height) = assets.overlay_size((10, 10), &text).unwrap();
+        assert!(width > 200 && height > 100, "{}x{}", width, height);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
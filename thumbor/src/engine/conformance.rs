@@ -0,0 +1,190 @@
+//! Every spec runs on every engine, and the engines have to agree on the result
+
+use super::*;
+use crate::format::Encoding;
+use image::RgbaImage;
+
+const GOPHER: &[u8] = include_bytes!("../../gopher.png");
+
+const ENGINES: [EngineKind; 2] = [EngineKind::Photon, EngineKind::ImageRs];
+
+/// Adding a spec breaks the build here until it has cases below
+fn name(spec: &Spec) -> &'static str {
+    match spec.data.as_ref().unwrap() {
+        spec::Data::Resize(_) => "resize",
+        spec::Data::Watermark(_) => "watermark",
+        spec::Data::Fliph(_) => "fliph",
+        spec::Data::Flipv(_) => "flipv",
+        spec::Data::Filter(_) => "filter",
+        spec::Data::Crop(_) => "crop",
+        spec::Data::Rotate(_) => "rotate",
+        spec::Data::Fit(_) => "fit",
+        spec::Data::Smartcrop(_) => "smartcrop",
+        spec::Data::Text(_) => "text",
+    }
+}
+
+/// Specs with the size they turn the 500x500 gopher into, and whether both
+/// engines have to produce the same pixels. Filters and seam carving are
+/// implemented differently, so only their size has to match.
+fn cases() -> Vec<(Spec, (u32, u32), bool)> {
+    vec![
+        (
+            Spec::new_resize(100, 80, resize::SampleFilter::Nearest),
+            (100, 80),
+            true,
+        ),
+        (
+            Spec::new_resize(120, 90, resize::SampleFilter::Lanczos3),
+            (120, 90),
+            false,
+        ),
+        (Spec::new_resize_seam_carve(400, 450), (400, 450), false),
+        (Spec::new_watermark(20, 20), (500, 500), true),
+        (
+            Spec::new_named_watermark("", watermark::Anchor::BottomRight, 10, 0.2, 0.5),
+            (500, 500),
+            true,
+        ),
+        (
+            Spec::new_text(
+                "hello\nworld",
+                32.0,
+                0xff0000ff,
+                watermark::Anchor::Center,
+                0,
+            ),
+            (500, 500),
+            true,
+        ),
+        (Spec::new_fliph(), (500, 500), true),
+        (Spec::new_flipv(), (500, 500), true),
+        (
+            Spec::new_filter(filter::Filter::Flagblue),
+            (500, 500),
+            false,
+        ),
+        (Spec::new_filter(filter::Filter::Liquid), (500, 500), false),
+        (
+            Spec::new_filter(filter::Filter::Twenties),
+            (500, 500),
+            false,
+        ),
+        (Spec::new_crop(10, 20, 300, 200), (300, 200), true),
+        (Spec::new_crop(400, 400, 300, 300), (100, 100), true),
+        (Spec::new_rotate(90.0), (500, 500), true),
+        (Spec::new_rotate(30.0), rotated_size((500, 500), 30.0), true),
+        (
+            Spec::new_fit(200, 100, fit::FitMode::Cover, Gravity::North),
+            (200, 100),
+            true,
+        ),
+        (
+            Spec::new_fit(100, 200, fit::FitMode::Contain, Gravity::Center),
+            (100, 200),
+            true,
+        ),
+        (Spec::new_smart_crop(64, 48), (64, 48), true),
+    ]
+}
+
+fn run(engine: EngineKind, specs: &[Spec], format: Encoding) -> Vec<u8> {
+    engine
+        .process(
+            Bytes::from_static(GOPHER),
+            BUILTIN_ASSETS.clone(),
+            specs,
+            format,
+        )
+        .unwrap_or_else(|e| panic!("{:?} failed on {:?}: {}", engine, specs, e))
+}
+
+fn decode(data: &[u8]) -> RgbaImage {
+    image::load_from_memory(data).unwrap().into_rgba8()
+}
+
+#[test]
+fn cases_should_cover_every_spec() {
+    let mut covered: Vec<_> = cases().iter().map(|(spec, ..)| name(spec)).collect();
+    covered.dedup();
+    assert_eq!(
+        covered,
+        [
+            "resize",
+            "watermark",
+            "text",
+            "fliph",
+            "flipv",
+            "filter",
+            "crop",
+            "rotate",
+            "fit",
+            "smartcrop"
+        ]
+    );
+}
+
+#[test]
+fn every_spec_should_run_on_every_engine() {
+    for (spec, size, same) in cases() {
+        let results: Vec<_> = ENGINES
+            .iter()
+            .map(|&engine| {
+                let img = decode(&run(engine, std::slice::from_ref(&spec), Encoding::Png));
+                assert_eq!(img.dimensions(), size, "{:?} on {}", engine, name(&spec));
+                img
+            })
+            .collect();
+
+        if same {
+            let diff: u64 = results[0]
+                .as_raw()
+                .iter()
+                .zip(results[1].as_raw())
+                .map(|(a, b)| a.abs_diff(*b) as u64)
+                .sum();
+            let mean = diff as f64 / results[0].as_raw().len() as f64;
+            assert!(mean < 1.0, "engines differ by {} on {:?}", mean, spec);
+        }
+    }
+}
+
+#[test]
+fn every_engine_should_encode_every_format() {
+    let specs = [
+        Spec::new_resize(64, 64, resize::SampleFilter::Triangle),
+        Spec::new_rotate(90.0),
+        Spec::new_text("hi", 12.0, 0, watermark::Anchor::TopLeft, 2),
+    ];
+    let formats = [
+        Encoding::Png,
+        Encoding::Jpeg(85),
+        Encoding::WebP,
+        Encoding::Gif,
+        #[cfg(feature = "avif")]
+        Encoding::Avif(60),
+    ];
+    for engine in ENGINES {
+        for format in formats {
+            let data = run(engine, &specs, format);
+            // avif has an encoder but no decoder
+            #[cfg(feature = "avif")]
+            if matches!(format, Encoding::Avif(_)) {
+                assert!(!data.is_empty());
+                continue;
+            }
+            let img = decode(&data);
+            assert_eq!(img.dimensions(), (64, 64), "{:?} as {:?}", engine, format);
+        }
+    }
+}
+
+#[test]
+fn engine_should_parse_from_config() {
+    assert_eq!("photon".parse::<EngineKind>().unwrap(), EngineKind::Photon);
+    assert_eq!("image".parse::<EngineKind>().unwrap(), EngineKind::ImageRs);
+    assert!("magick".parse::<EngineKind>().is_err());
+    for engine in ENGINES {
+        assert_eq!(engine.as_str().parse::<EngineKind>().unwrap(), engine);
+    }
+}
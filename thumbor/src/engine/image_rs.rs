@@ -0,0 +1,177 @@
+use super::{check, ops, Assets, Engine, SpecTransformer, BUILTIN_ASSETS};
+use crate::format::Encoding;
+use crate::pb::*;
+use anyhow::Result;
+use bytes::Bytes;
+use image::{imageops::FilterType, DynamicImage, RgbaImage};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+/// ImageRs works on the decoded image as it is, so resizes and flips keep
+/// grayscale and 16-bit images as they came in
+pub struct ImageRs {
+    img: DynamicImage,
+    assets: Arc<Assets>,
+}
+
+impl Deref for ImageRs {
+    type Target = DynamicImage;
+
+    fn deref(&self) -> &Self::Target {
+        &self.img
+    }
+}
+
+impl DerefMut for ImageRs {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.img
+    }
+}
+
+impl TryFrom<Bytes> for ImageRs {
+    type Error = anyhow::Error;
+
+    fn try_from(data: Bytes) -> Result<Self, Self::Error> {
+        Ok(Self::new(image::load_from_memory(&data)?))
+    }
+}
+
+impl ImageRs {
+    pub fn new(img: DynamicImage) -> Self {
+        Self {
+            img,
+            assets: BUILTIN_ASSETS.clone(),
+        }
+    }
+
+    /// Take watermarks and the font from `assets` instead of the built-ins
+    pub fn with_assets(mut self, assets: Arc<Assets>) -> Self {
+        self.assets = assets;
+        self
+    }
+
+    fn set_rgba(&mut self, img: RgbaImage) {
+        self.img = DynamicImage::ImageRgba8(img);
+    }
+}
+
+impl Engine for ImageRs {
+    fn apply(&mut self, specs: &[Spec]) -> Result<()> {
+        for spec in specs.iter() {
+            check(spec)?;
+            match spec.data {
+                Some(spec::Data::Resize(ref v)) => self.transform(v),
+                Some(spec::Data::Watermark(ref v)) => self.transform(v),
+                Some(spec::Data::Fliph(ref v)) => self.transform(v),
+                Some(spec::Data::Flipv(ref v)) => self.transform(v),
+                Some(spec::Data::Filter(ref v)) => self.transform(v),
+                Some(spec::Data::Crop(ref v)) => self.transform(v),
+                Some(spec::Data::Rotate(ref v)) => self.transform(v),
+                Some(spec::Data::Fit(ref v)) => self.transform(v),
+                Some(spec::Data::Smartcrop(ref v)) => self.transform(v),
+                Some(spec::Data::Text(ref v)) => self.transform(v),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn generate(self, format: Encoding) -> Result<Vec<u8>> {
+        // png takes every color type, jpeg has no alpha and the other
+        // encoders only take 8-bit rgb or rgba
+        let img = match format {
+            Encoding::Png => self.img,
+            Encoding::Jpeg(_) => DynamicImage::ImageRgb8(self.img.into_rgb8()),
+            _ if !self.img.color().has_alpha() => DynamicImage::ImageRgb8(self.img.into_rgb8()),
+            _ => DynamicImage::ImageRgba8(self.img.into_rgba8()),
+        };
+
+        Ok(format.encode(&img)?)
+    }
+}
+
+impl SpecTransformer<&Resize> for ImageRs {
+    fn transform(&mut self, op: &Resize) {
+        match op.rtype() {
+            resize::ResizeType::Normal => {
+                let filter: FilterType = op.filter().into();
+                self.img = self.img.resize_exact(op.width, op.height, filter);
+            }
+            resize::ResizeType::SeamCarve => {
+                let img = ops::seam_carve(&self.img.to_rgba8(), op.width, op.height);
+                self.set_rgba(img);
+            }
+        }
+    }
+}
+
+impl SpecTransformer<&Watermark> for ImageRs {
+    fn transform(&mut self, op: &Watermark) {
+        let assets = self.assets.clone();
+        if let Some(mark) = assets.watermark(&op.name) {
+            let mut img = self.img.to_rgba8();
+            ops::watermark(&mut img, mark, op);
+            self.set_rgba(img);
+        }
+    }
+}
+
+impl SpecTransformer<&TextOverlay> for ImageRs {
+    fn transform(&mut self, op: &TextOverlay) {
+        let assets = self.assets.clone();
+        let mut img = self.img.to_rgba8();
+        ops::text(&mut img, assets.font(), op);
+        self.set_rgba(img);
+    }
+}
+
+impl SpecTransformer<&Fliph> for ImageRs {
+    fn transform(&mut self, _op: &Fliph) {
+        self.img = self.img.fliph();
+    }
+}
+
+impl SpecTransformer<&Flipv> for ImageRs {
+    fn transform(&mut self, _op: &Flipv) {
+        self.img = self.img.flipv();
+    }
+}
+
+impl SpecTransformer<&Filter> for ImageRs {
+    fn transform(&mut self, op: &Filter) {
+        let mut img = self.img.to_rgba8();
+        ops::filter(&mut img, op.filter());
+        self.set_rgba(img);
+    }
+}
+
+impl SpecTransformer<&Crop> for ImageRs {
+    fn transform(&mut self, op: &Crop) {
+        if let Some(img) = ops::crop(&self.img.to_rgba8(), op) {
+            self.set_rgba(img);
+        }
+    }
+}
+
+impl SpecTransformer<&Rotate> for ImageRs {
+    fn transform(&mut self, op: &Rotate) {
+        let img = ops::rotate(&self.img.to_rgba8(), op.degrees);
+        self.set_rgba(img);
+    }
+}
+
+impl SpecTransformer<&Fit> for ImageRs {
+    fn transform(&mut self, op: &Fit) {
+        if let Some(img) = ops::fit(&self.img.to_rgba8(), op) {
+            self.set_rgba(img);
+        }
+    }
+}
+
+impl SpecTransformer<&SmartCrop> for ImageRs {
+    fn transform(&mut self, op: &SmartCrop) {
+        if let Some(img) = ops::smart_crop(&self.img.to_rgba8(), op) {
+            self.set_rgba(img);
+        }
+    }
+}
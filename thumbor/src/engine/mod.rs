@@ -1,15 +1,27 @@
-use crate::pb::Spec;
-use image::ImageOutputFormat;
+use crate::format::Encoding;
+use crate::pb::*;
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use std::str::FromStr;
+use std::sync::Arc;
 
+mod assets;
+#[cfg(test)]
+mod conformance;
+mod image_rs;
+mod ops;
 mod photon;
+pub use assets::{Assets, BUILTIN_ASSETS};
+pub use image_rs::ImageRs;
+pub use ops::rotated_size;
 pub use photon::Photon;
 
 /// Engine represents image process engine
 pub trait Engine {
-    /// Process according to spec order
-    fn apply(&mut self, specs: &[Spec]);
+    /// Process according to spec order, fails on specs it can't apply
+    fn apply(&mut self, specs: &[Spec]) -> Result<()>;
     /// generate target image with vec format
-    fn generate(self, format: ImageOutputFormat) -> Vec<u8>;
+    fn generate(self, format: Encoding) -> Result<Vec<u8>>;
 }
 
 /// SpecTransformer is used to transform
@@ -17,3 +29,78 @@ pub trait Engine {
 pub trait SpecTransformer<T> {
     fn transform(&mut self, operator: T);
 }
+
+/// prost keeps enum fields as plain integers, reject values the proto does
+/// not define instead of guessing what they mean
+fn check(spec: &Spec) -> Result<()> {
+    let known = match spec.data {
+        Some(spec::Data::Resize(ref v)) => {
+            resize::ResizeType::from_i32(v.rtype).is_some()
+                && resize::SampleFilter::from_i32(v.filter).is_some()
+        }
+        Some(spec::Data::Filter(ref v)) => filter::Filter::from_i32(v.filter).is_some(),
+        Some(spec::Data::Fit(ref v)) => {
+            fit::FitMode::from_i32(v.mode).is_some() && Gravity::from_i32(v.gravity).is_some()
+        }
+        Some(spec::Data::Watermark(ref v)) => watermark::Anchor::from_i32(v.anchor).is_some(),
+        Some(spec::Data::Text(ref v)) => watermark::Anchor::from_i32(v.anchor).is_some(),
+        Some(_) => true,
+        None => bail!("empty spec"),
+    };
+    if !known {
+        bail!("unknown option in {:?}", spec);
+    }
+    Ok(())
+}
+
+/// EngineKind picks the engine that processes images
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EngineKind {
+    #[default]
+    Photon,
+    ImageRs,
+}
+
+impl FromStr for EngineKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "photon" => Ok(EngineKind::Photon),
+            "image" => Ok(EngineKind::ImageRs),
+            _ => bail!("unknown engine {}, expected photon or image", s),
+        }
+    }
+}
+
+impl EngineKind {
+    /// Name used in the config, the inverse of `from_str`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EngineKind::Photon => "photon",
+            EngineKind::ImageRs => "image",
+        }
+    }
+
+    /// Decode `data`, apply `specs` and encode the result as `format`
+    pub fn process(
+        self,
+        data: Bytes,
+        assets: Arc<Assets>,
+        specs: &[Spec],
+        format: Encoding,
+    ) -> Result<Vec<u8>> {
+        match self {
+            EngineKind::Photon => {
+                let mut engine = Photon::try_from(data)?.with_assets(assets);
+                engine.apply(specs)?;
+                engine.generate(format)
+            }
+            EngineKind::ImageRs => {
+                let mut engine = ImageRs::try_from(data)?.with_assets(assets);
+                engine.apply(specs)?;
+                engine.generate(format)
+            }
+        }
+    }
+}
@@ -0,0 +1,707 @@
+//! Pixel operations on plain rgba buffers, shared by the engines
+use crate::pb::*;
+use ab_glyph::{point, Font, FontRef, Glyph, PxScale, ScaleFont};
+use image::{imageops, imageops::FilterType, Rgba, RgbaImage};
+
+const TRANSPARENT: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+/// Cut `op` out of `img`, clamped to its bounds. None if nothing is left
+pub fn crop(img: &RgbaImage, op: &Crop) -> Option<RgbaImage> {
+    let (w, h) = img.dimensions();
+    if op.x >= w || op.y >= h {
+        return None;
+    }
+    let width = op.width.min(w - op.x);
+    let height = op.height.min(h - op.y);
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some(imageops::crop_imm(img, op.x, op.y, width, height).to_image())
+}
+
+/// Rotate clockwise, the canvas grows to hold the rotated image and the
+/// corners it uncovers are transparent
+pub fn rotate(img: &RgbaImage, degrees: f32) -> RgbaImage {
+    let degrees = degrees.rem_euclid(360.0);
+    if degrees == 0.0 {
+        return img.clone();
+    } else if degrees == 90.0 {
+        return imageops::rotate90(img);
+    } else if degrees == 180.0 {
+        return imageops::rotate180(img);
+    } else if degrees == 270.0 {
+        return imageops::rotate270(img);
+    }
+
+    let (sin, cos) = (degrees as f64).to_radians().sin_cos();
+    let (w, h) = (img.width() as f64, img.height() as f64);
+    let (out_w, out_h) = rotated_size(img.dimensions(), degrees);
+
+    let (cx, cy) = (w / 2.0, h / 2.0);
+    let (ox, oy) = (out_w as f64 / 2.0, out_h as f64 / 2.0);
+    RgbaImage::from_fn(out_w, out_h, |x, y| {
+        // map the center of each output pixel back into the source
+        let dx = x as f64 + 0.5 - ox;
+        let dy = y as f64 + 0.5 - oy;
+        let sx = dx * cos + dy * sin + cx - 0.5;
+        let sy = -dx * sin + dy * cos + cy - 0.5;
+        bilinear(img, sx, sy)
+    })
+}
+
+/// Size of the canvas `rotate` returns
+pub fn rotated_size(size: (u32, u32), degrees: f32) -> (u32, u32) {
+    let degrees = degrees.rem_euclid(360.0);
+    if degrees == 90.0 || degrees == 270.0 {
+        return (size.1, size.0);
+    } else if degrees == 0.0 || degrees == 180.0 {
+        return size;
+    }
+    let (sin, cos) = (degrees as f64).to_radians().sin_cos();
+    let (w, h) = (size.0 as f64, size.1 as f64);
+    (
+        (w * cos.abs() + h * sin.abs()).ceil() as u32,
+        (w * sin.abs() + h * cos.abs()).ceil() as u32,
+    )
+}
+
+fn bilinear(img: &RgbaImage, x: f64, y: f64) -> Rgba<u8> {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let pixel = |x: f64, y: f64| {
+        if x < 0.0 || y < 0.0 || x >= img.width() as f64 || y >= img.height() as f64 {
+            TRANSPARENT
+        } else {
+            *img.get_pixel(x as u32, y as u32)
+        }
+    };
+    let corners = [
+        (pixel(x0, y0), (1.0 - fx) * (1.0 - fy)),
+        (pixel(x0 + 1.0, y0), fx * (1.0 - fy)),
+        (pixel(x0, y0 + 1.0), (1.0 - fx) * fy),
+        (pixel(x0 + 1.0, y0 + 1.0), fx * fy),
+    ];
+    let mut out = [0u8; 4];
+    for (i, channel) in out.iter_mut().enumerate() {
+        let v: f64 = corners.iter().map(|(p, w)| p[i] as f64 * w).sum();
+        *channel = v.round().clamp(0.0, 255.0) as u8;
+    }
+    Rgba(out)
+}
+
+/// Offset that places an `inner` box inside `outer` according to `gravity`
+pub fn anchor(outer: (u32, u32), inner: (u32, u32), gravity: Gravity) -> (u32, u32) {
+    let free_x = outer.0.saturating_sub(inner.0);
+    let free_y = outer.1.saturating_sub(inner.1);
+    // 0 is left/top, 1 is centered, 2 is right/bottom
+    let (fx, fy) = match gravity {
+        Gravity::Center => (1, 1),
+        Gravity::North => (1, 0),
+        Gravity::NorthEast => (2, 0),
+        Gravity::East => (2, 1),
+        Gravity::SouthEast => (2, 2),
+        Gravity::South => (1, 2),
+        Gravity::SouthWest => (0, 2),
+        Gravity::West => (0, 1),
+        Gravity::NorthWest => (0, 0),
+    };
+    (free_x * fx / 2, free_y * fy / 2)
+}
+
+/// Size `size` scales to, keeping its aspect, to cover or fit in `target`
+pub fn scale_to(size: (u32, u32), target: (u32, u32), mode: fit::FitMode) -> (u32, u32) {
+    let (w, h) = (size.0 as f64, size.1 as f64);
+    let (sx, sy) = (target.0 as f64 / w, target.1 as f64 / h);
+    let scale = match mode {
+        fit::FitMode::Cover => sx.max(sy),
+        fit::FitMode::Contain => sx.min(sy),
+    };
+    let scaled = (
+        ((w * scale).round() as u32).max(1),
+        ((h * scale).round() as u32).max(1),
+    );
+    match mode {
+        // rounding must not leave a gap along the tight side
+        fit::FitMode::Cover => (scaled.0.max(target.0), scaled.1.max(target.1)),
+        fit::FitMode::Contain => (scaled.0.min(target.0), scaled.1.min(target.1)),
+    }
+}
+
+/// Scale `img` to exactly `op.width`x`op.height`. Cover crops the overflow,
+/// contain pads with transparency, both anchored by gravity
+pub fn fit(img: &RgbaImage, op: &Fit) -> Option<RgbaImage> {
+    if op.width == 0 || op.height == 0 {
+        return None;
+    }
+    let target = (op.width, op.height);
+    let (w, h) = scale_to(img.dimensions(), target, op.mode());
+    let scaled = imageops::resize(img, w, h, FilterType::Lanczos3);
+
+    let img = match op.mode() {
+        fit::FitMode::Cover => {
+            let (x, y) = anchor((w, h), target, op.gravity());
+            imageops::crop_imm(&scaled, x, y, op.width, op.height).to_image()
+        }
+        fit::FitMode::Contain => {
+            let (x, y) = anchor(target, (w, h), op.gravity());
+            let mut canvas = RgbaImage::from_pixel(op.width, op.height, TRANSPARENT);
+            imageops::overlay(&mut canvas, &scaled, x as i64, y as i64);
+            canvas
+        }
+    };
+    Some(img)
+}
+
+/// Crop to the most detailed region with the aspect of `op`, then scale to it
+pub fn smart_crop(img: &RgbaImage, op: &SmartCrop) -> Option<RgbaImage> {
+    if op.width == 0 || op.height == 0 {
+        return None;
+    }
+    let (x, y, w, h) = smart_region(img, op.width, op.height);
+    let region = imageops::crop_imm(img, x, y, w, h).to_image();
+    Some(imageops::resize(
+        &region,
+        op.width,
+        op.height,
+        FilterType::Lanczos3,
+    ))
+}
+
+/// The largest window with the aspect of `width`x`height`, placed where the
+/// luma gradient (edges, texture) is strongest. Flat images keep the center
+pub fn smart_region(img: &RgbaImage, width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let (w, h) = img.dimensions();
+    let (cw, ch) = if w as u64 * height as u64 > h as u64 * width as u64 {
+        ((h as u64 * width as u64 / height as u64) as u32, h)
+    } else {
+        (w, (w as u64 * height as u64 / width as u64) as u32)
+    };
+    let (cw, ch) = (cw.clamp(1, w), ch.clamp(1, h));
+
+    let luma: Vec<i64> = img
+        .pixels()
+        .map(|p| {
+            let l = (299 * p[0] as i64 + 587 * p[1] as i64 + 114 * p[2] as i64) / 1000;
+            // transparent pixels carry no detail
+            l * p[3] as i64 / 255
+        })
+        .collect();
+    let at = |x: u32, y: u32| luma[(y * w + x) as usize];
+
+    // summed-area table of the energy, one row and column of padding
+    let stride = w as usize + 1;
+    let mut sat = vec![0i64; stride * (h as usize + 1)];
+    for y in 0..h {
+        let mut row = 0;
+        for x in 0..w {
+            let right = at((x + 1).min(w - 1), y);
+            let below = at(x, (y + 1).min(h - 1));
+            row += (right - at(x, y)).abs() + (below - at(x, y)).abs();
+            let i = (y as usize + 1) * stride + x as usize + 1;
+            sat[i] = sat[i - stride] + row;
+        }
+    }
+    let score = |x: u32, y: u32| {
+        let (x0, y0) = (x as usize, y as usize);
+        let (x1, y1) = (x0 + cw as usize, y0 + ch as usize);
+        sat[y1 * stride + x1] - sat[y0 * stride + x1] - sat[y1 * stride + x0]
+            + sat[y0 * stride + x0]
+    };
+
+    // the window only slides along one axis, start centered so ties stay there
+    let (free_x, free_y) = (w - cw, h - ch);
+    let mut best = (free_x / 2, free_y / 2);
+    let mut best_score = score(best.0, best.1);
+    for offset in 0..=free_x.max(free_y) {
+        let (x, y) = (offset.min(free_x), offset.min(free_y));
+        let s = score(x, y);
+        if s > best_score {
+            best = (x, y);
+            best_score = s;
+        }
+    }
+    (best.0, best.1, cw, ch)
+}
+
+/// Content-aware resize: remove the lowest energy seams until `width` x
+/// `height` is reached. Like photon's, it only shrinks, a side that is
+/// already small enough stays as it is
+pub fn seam_carve(img: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    let mut img = img.clone();
+    while img.width() > width.max(1) {
+        img = remove_seam(&img);
+    }
+    if img.height() > height.max(1) {
+        // carve rows as columns of the transposed image
+        let mut turned = imageops::rotate90(&img);
+        while turned.width() > height.max(1) {
+            turned = remove_seam(&turned);
+        }
+        img = imageops::rotate270(&turned);
+    }
+    img
+}
+
+/// Remove the connected top to bottom path of pixels with the least gradient
+fn remove_seam(img: &RgbaImage) -> RgbaImage {
+    let (w, h) = (img.width() as usize, img.height() as usize);
+    let luma: Vec<i64> = img
+        .pixels()
+        .map(|p| (299 * p[0] as i64 + 587 * p[1] as i64 + 114 * p[2] as i64) / 1000)
+        .collect();
+    let at = |x: usize, y: usize| luma[y * w + x];
+
+    // cost[i] is the cheapest seam from the top row down to pixel i
+    let mut cost = vec![0i64; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            // differences to each neighbour, a central difference can't
+            // see fine detail like a checkerboard
+            let c = at(x, y);
+            let energy = (c - at(x.saturating_sub(1), y)).abs()
+                + (c - at((x + 1).min(w - 1), y)).abs()
+                + (c - at(x, y.saturating_sub(1))).abs()
+                + (c - at(x, (y + 1).min(h - 1))).abs();
+            let above = if y == 0 {
+                0
+            } else {
+                let row = &cost[(y - 1) * w..y * w];
+                row[x.saturating_sub(1)..=(x + 1).min(w - 1)]
+                    .iter()
+                    .copied()
+                    .min()
+                    .unwrap()
+            };
+            cost[y * w + x] = energy + above;
+        }
+    }
+
+    // walk back up from the cheapest end
+    let mut seam = vec![0usize; h];
+    let last = &cost[(h - 1) * w..];
+    seam[h - 1] = (0..w).min_by_key(|&x| last[x]).unwrap();
+    for y in (0..h - 1).rev() {
+        let x = seam[y + 1];
+        let row = &cost[y * w..(y + 1) * w];
+        seam[y] = (x.saturating_sub(1)..=(x + 1).min(w - 1))
+            .min_by_key(|&x| row[x])
+            .unwrap();
+    }
+
+    RgbaImage::from_fn(w as u32 - 1, h as u32, |x, y| {
+        let skip = (x as usize >= seam[y as usize]) as u32;
+        *img.get_pixel(x + skip, y)
+    })
+}
+
+/// Approximations of photon's color filters, for engines that lack them
+pub fn filter(img: &mut RgbaImage, filter: filter::Filter) {
+    let tint = |img: &mut RgbaImage, color: [u8; 3]| {
+        for p in img.pixels_mut() {
+            for (channel, tint) in p.0.iter_mut().zip(color) {
+                *channel = overlay(*channel, tint);
+            }
+        }
+    };
+    match filter {
+        filter::Filter::Flagblue => tint(img, [0, 0, 131]),
+        filter::Filter::Liquid => tint(img, [0, 10, 75]),
+        filter::Filter::Twenties => {
+            for p in img.pixels_mut() {
+                let [r, g, b, _] = p.0.map(|c| c as f32);
+                let sepia = [
+                    0.393 * r + 0.769 * g + 0.189 * b,
+                    0.349 * r + 0.686 * g + 0.168 * b,
+                    0.272 * r + 0.534 * g + 0.131 * b,
+                ];
+                for (channel, v) in p.0.iter_mut().zip(sepia) {
+                    *channel = v.round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+        filter::Filter::Unknown => {}
+    }
+}
+
+/// The overlay blend mode, darkens darks and lightens lights toward `top`
+fn overlay(base: u8, top: u8) -> u8 {
+    let (b, t) = (base as f32 / 255.0, top as f32 / 255.0);
+    let v = if b < 0.5 {
+        2.0 * b * t
+    } else {
+        1.0 - 2.0 * (1.0 - b) * (1.0 - t)
+    };
+    (v * 255.0).round() as u8
+}
+
+/// Position of an `inner` box `margin` pixels from the `anchor` of `outer`,
+/// it may stick out of `outer`
+pub fn place(
+    outer: (u32, u32),
+    inner: (u32, u32),
+    anchor: watermark::Anchor,
+    margin: (u32, u32),
+) -> (i64, i64) {
+    let (ow, oh) = (outer.0 as i64, outer.1 as i64);
+    let (iw, ih) = (inner.0 as i64, inner.1 as i64);
+    let (mx, my) = (margin.0 as i64, margin.1 as i64);
+    match anchor {
+        watermark::Anchor::TopLeft => (mx, my),
+        watermark::Anchor::TopRight => (ow - iw - mx, my),
+        watermark::Anchor::BottomLeft => (mx, oh - ih - my),
+        watermark::Anchor::BottomRight => (ow - iw - mx, oh - ih - my),
+        watermark::Anchor::Center => ((ow - iw) / 2 + mx, (oh - ih) / 2 + my),
+    }
+}
+
+/// Size a `mark` watermark is drawn at on an image of `size`
+pub fn watermark_size(size: (u32, u32), mark: (u32, u32), scale: f32) -> (u32, u32) {
+    if scale <= 0.0 {
+        return mark;
+    }
+    let width = ((size.0 as f32 * scale).round() as u32).max(1);
+    let height = (mark.1 as u64 * width as u64 / mark.0 as u64).max(1);
+    (width, height.min(u32::MAX as u64) as u32)
+}
+
+/// Blend `mark` onto `img`, scaled and faded as `op` asks
+pub fn watermark(img: &mut RgbaImage, mark: &RgbaImage, op: &Watermark) {
+    let (width, height) = watermark_size(img.dimensions(), mark.dimensions(), op.scale);
+    let mut mark = if (width, height) != mark.dimensions() {
+        imageops::resize(mark, width, height, FilterType::Lanczos3)
+    } else {
+        mark.clone()
+    };
+    let opacity = op.opacity.unwrap_or(1.0).clamp(0.0, 1.0);
+    if opacity < 1.0 {
+        for p in mark.pixels_mut() {
+            p[3] = (p[3] as f32 * opacity).round() as u8;
+        }
+    }
+
+    let (x, y) = place(
+        img.dimensions(),
+        mark.dimensions(),
+        op.anchor(),
+        (op.x, op.y),
+    );
+    imageops::overlay(img, &mark, x, y);
+}
+
+/// Size of the canvas `text` draws `op` on
+pub fn text_size(font: &FontRef, op: &TextOverlay) -> (u32, u32) {
+    layout(font, op).1
+}
+
+/// Place every glyph of `op.text`, one line per `\n`, and measure them
+fn layout(font: &FontRef, op: &TextOverlay) -> (Vec<Glyph>, (u32, u32)) {
+    let size = if op.size > 0.0 { op.size } else { 24.0 };
+    let scale = PxScale::from(size);
+    let font = font.as_scaled(scale);
+    let line_height = font.ascent() - font.descent() + font.line_gap();
+
+    // lay out every glyph first, the canvas is as large as the text
+    let mut glyphs = Vec::new();
+    let mut width: f32 = 0.0;
+    for (i, line) in op.text.lines().enumerate() {
+        let y = i as f32 * line_height + font.ascent();
+        let mut caret = 0.0;
+        let mut prev = None;
+        for c in line.chars() {
+            let id = font.glyph_id(c);
+            if let Some(prev) = prev {
+                caret += font.kern(prev, id);
+            }
+            glyphs.push(id.with_scale_and_position(scale, point(caret, y)));
+            caret += font.h_advance(id);
+            prev = Some(id);
+        }
+        width = width.max(caret);
+    }
+    let lines = op.text.lines().count().max(1);
+    let height = lines as f32 * line_height - font.line_gap();
+    let size = ((width.ceil() as u32).max(1), (height.ceil() as u32).max(1));
+    (glyphs, size)
+}
+
+/// Draw `op.text` onto `img`, one line per `\n`
+pub fn text(img: &mut RgbaImage, font: &FontRef, op: &TextOverlay) {
+    if op.text.is_empty() {
+        return;
+    }
+    let [r, g, b, a] = match op.color {
+        0 => [255; 4],
+        color => color.to_be_bytes(),
+    };
+
+    let (glyphs, (width, height)) = layout(font, op);
+    let mut canvas = RgbaImage::from_pixel(width, height, TRANSPARENT);
+    for glyph in glyphs {
+        let Some(outline) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|x, y, coverage| {
+            let x = bounds.min.x as i64 + x as i64;
+            let y = bounds.min.y as i64 + y as i64;
+            if x < 0 || y < 0 || x >= canvas.width() as i64 || y >= canvas.height() as i64 {
+                return;
+            }
+            let alpha = (a as f32 * coverage.clamp(0.0, 1.0)).round() as u8;
+            let pixel = canvas.get_pixel_mut(x as u32, y as u32);
+            // neighbouring glyphs may overlap by a pixel, keep the stronger one
+            if alpha > pixel[3] {
+                *pixel = Rgba([r, g, b, alpha]);
+            }
+        });
+    }
+
+    let (x, y) = place(
+        img.dimensions(),
+        canvas.dimensions(),
+        op.anchor(),
+        (op.x, op.y),
+    );
+    imageops::overlay(img, &canvas, x, y);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    #[test]
+    fn crop_should_clamp_to_bounds() {
+        let img = RgbaImage::from_pixel(10, 8, RED);
+        let crop = |x, y, width, height| Crop {
+            x,
+            y,
+            width,
+            height,
+        };
+
+        assert_eq!(
+            super::crop(&img, &crop(2, 2, 4, 3)).unwrap().dimensions(),
+            (4, 3)
+        );
+        assert_eq!(
+            super::crop(&img, &crop(6, 4, 100, 100))
+                .unwrap()
+                .dimensions(),
+            (4, 4)
+        );
+        assert!(super::crop(&img, &crop(10, 0, 1, 1)).is_none());
+        assert!(super::crop(&img, &crop(0, 0, 0, 1)).is_none());
+    }
+
+    #[test]
+    fn rotate_should_handle_right_and_arbitrary_angles() {
+        let mut img = RgbaImage::from_pixel(4, 2, RED);
+        img.put_pixel(0, 0, WHITE);
+
+        let r = rotate(&img, 90.0);
+        assert_eq!(r.dimensions(), (2, 4));
+        assert_eq!(*r.get_pixel(1, 0), WHITE);
+        assert_eq!(rotate(&img, -270.0), r);
+        assert_eq!(rotate(&img, 360.0), img);
+
+        let r = rotate(&RgbaImage::from_pixel(10, 10, RED), 45.0);
+        assert_eq!(r.dimensions(), (15, 15));
+        assert_eq!(rotated_size((4, 2), 90.0), (2, 4));
+        assert_eq!(rotated_size((4, 2), -180.0), (4, 2));
+        assert_eq!(*r.get_pixel(0, 0), TRANSPARENT);
+        assert_eq!(*r.get_pixel(7, 7), RED);
+    }
+
+    #[test]
+    fn anchor_should_follow_gravity() {
+        assert_eq!(anchor((10, 10), (4, 4), Gravity::Center), (3, 3));
+        assert_eq!(anchor((10, 10), (4, 4), Gravity::NorthWest), (0, 0));
+        assert_eq!(anchor((10, 10), (4, 4), Gravity::SouthEast), (6, 6));
+        assert_eq!(anchor((10, 10), (4, 4), Gravity::East), (6, 3));
+        assert_eq!(anchor((4, 4), (10, 10), Gravity::SouthEast), (0, 0));
+    }
+
+    #[test]
+    fn fit_should_produce_exact_size() {
+        let img = RgbaImage::from_pixel(300, 200, RED);
+        let fit = |mode: fit::FitMode| Fit {
+            width: 100,
+            height: 100,
+            mode: mode as i32,
+            gravity: Gravity::North as i32,
+        };
+
+        assert_eq!(
+            scale_to((300, 200), (100, 100), fit::FitMode::Cover),
+            (150, 100)
+        );
+        assert_eq!(
+            scale_to((300, 200), (100, 100), fit::FitMode::Contain),
+            (100, 67)
+        );
+
+        let cover = super::fit(&img, &fit(fit::FitMode::Cover)).unwrap();
+        assert_eq!(cover.dimensions(), (100, 100));
+        assert_eq!(*cover.get_pixel(50, 99), RED);
+
+        // contain anchored north leaves the padding at the bottom
+        let contain = super::fit(&img, &fit(fit::FitMode::Contain)).unwrap();
+        assert_eq!(contain.dimensions(), (100, 100));
+        assert_eq!(*contain.get_pixel(50, 0), RED);
+        assert_eq!(*contain.get_pixel(50, 99), TRANSPARENT);
+    }
+
+    #[test]
+    fn smart_region_should_find_detail() {
+        // a flat image with a checkerboard in its right part
+        let img = RgbaImage::from_fn(300, 100, |x, y| {
+            if x >= 220 && (x + y) % 2 == 0 {
+                WHITE
+            } else {
+                RED
+            }
+        });
+        assert_eq!(smart_region(&img, 1, 1), (200, 0, 100, 100));
+
+        let flat = RgbaImage::from_pixel(300, 100, RED);
+        assert_eq!(smart_region(&flat, 1, 1), (100, 0, 100, 100));
+        assert_eq!(smart_region(&flat, 3, 1), (0, 0, 300, 100));
+
+        let out = smart_crop(
+            &img,
+            &SmartCrop {
+                width: 50,
+                height: 50,
+            },
+        )
+        .unwrap();
+        assert_eq!(out.dimensions(), (50, 50));
+    }
+
+    #[test]
+    fn seam_carve_should_keep_the_detail() {
+        // a flat image with a checkerboard in the middle third
+        let img = RgbaImage::from_fn(30, 20, |x, y| {
+            if (10..20).contains(&x) && (x + y) % 2 == 0 {
+                WHITE
+            } else {
+                RED
+            }
+        });
+
+        // the seams all go through the flat parts
+        let carved = seam_carve(&img, 20, 20);
+        assert_eq!(carved.dimensions(), (20, 20));
+        assert_eq!(carved.pixels().filter(|p| **p == WHITE).count(), 100);
+
+        assert_eq!(seam_carve(&img, 20, 15).dimensions(), (20, 15));
+        assert_eq!(seam_carve(&img, 40, 10).dimensions(), (30, 10));
+    }
+
+    #[test]
+    fn filter_should_tint() {
+        let mut img = RgbaImage::from_pixel(2, 2, Rgba([200, 200, 200, 255]));
+        filter(&mut img, filter::Filter::Flagblue);
+        let p = img.get_pixel(0, 0);
+        assert!(p[2] > p[0] && p[2] > p[1]);
+        assert_eq!(p[3], 255);
+
+        let mut img = RgbaImage::from_pixel(2, 2, Rgba([100, 100, 100, 255]));
+        filter(&mut img, filter::Filter::Twenties);
+        let p = img.get_pixel(0, 0);
+        assert!(p[0] > p[1] && p[1] > p[2]);
+
+        let mut img = RgbaImage::from_pixel(2, 2, RED);
+        filter(&mut img, filter::Filter::Unknown);
+        assert_eq!(*img.get_pixel(0, 0), RED);
+    }
+
+    #[test]
+    fn place_should_follow_anchor_and_margin() {
+        use watermark::Anchor;
+        assert_eq!(place((100, 50), (10, 10), Anchor::TopLeft, (4, 2)), (4, 2));
+        assert_eq!(
+            place((100, 50), (10, 10), Anchor::TopRight, (4, 2)),
+            (86, 2)
+        );
+        assert_eq!(
+            place((100, 50), (10, 10), Anchor::BottomLeft, (4, 2)),
+            (4, 38)
+        );
+        assert_eq!(
+            place((100, 50), (10, 10), Anchor::BottomRight, (4, 2)),
+            (86, 38)
+        );
+        assert_eq!(place((100, 50), (10, 10), Anchor::Center, (0, 0)), (45, 20));
+    }
+
+    #[test]
+    fn watermark_should_scale_and_fade() {
+        let mut img = RgbaImage::from_pixel(100, 50, RED);
+        let mark = RgbaImage::from_pixel(20, 10, WHITE);
+        let op = Watermark {
+            x: 0,
+            y: 0,
+            name: String::new(),
+            scale: 0.5,
+            opacity: Some(0.5),
+            anchor: watermark::Anchor::BottomRight as i32,
+        };
+        watermark(&mut img, &mark, &op);
+
+        // 50x25 in the bottom right corner, half way between red and white,
+        // give or take the rounding of the blend
+        let near = |p: &Rgba<u8>, q: [u8; 4]| p.0.iter().zip(q).all(|(a, b)| a.abs_diff(b) <= 1);
+        assert_eq!(*img.get_pixel(49, 24), RED);
+        assert!(near(img.get_pixel(50, 25), [255, 128, 128, 255]));
+        assert!(near(img.get_pixel(99, 49), [255, 128, 128, 255]));
+
+        // unset is opaque, 0 is invisible
+        let mut img = RgbaImage::from_pixel(100, 50, RED);
+        let op = Watermark {
+            opacity: None,
+            ..op
+        };
+        watermark(&mut img, &mark, &op);
+        assert_eq!(*img.get_pixel(99, 49), WHITE);
+        let mut img = RgbaImage::from_pixel(100, 50, RED);
+        watermark(
+            &mut img,
+            &mark,
+            &Watermark {
+                opacity: Some(0.0),
+                ..op
+            },
+        );
+        assert_eq!(*img.get_pixel(99, 49), RED);
+    }
+
+    #[test]
+    fn text_should_draw_in_anchor_corner() {
+        let font = FontRef::try_from_slice(include_bytes!("../../assets/DejaVuSans.ttf")).unwrap();
+        let mut img = RgbaImage::from_pixel(200, 100, RED);
+        let op = TextOverlay {
+            text: "Hi\nthere".to_string(),
+            size: 20.0,
+            color: 0x000000ff,
+            anchor: watermark::Anchor::TopLeft as i32,
+            x: 5,
+            y: 5,
+        };
+        text(&mut img, &font, &op);
+
+        let dark = |x0: u32, y0: u32, x1: u32, y1: u32| {
+            (x0..x1)
+                .flat_map(|x| (y0..y1).map(move |y| (x, y)))
+                .filter(|&(x, y)| img.get_pixel(x, y)[0] < 128)
+                .count()
+        };
+        assert!(dark(0, 0, 100, 60) > 50);
+        assert_eq!(dark(0, 0, 5, 100), 0);
+        assert_eq!(dark(100, 0, 200, 100), 0);
+        assert_eq!(dark(0, 60, 200, 100), 0);
+    }
+}
@@ -1,33 +1,30 @@
-use super::{Engine, SpecTransformer};
+use super::{check, ops, Assets, Engine, SpecTransformer, BUILTIN_ASSETS};
+use crate::format::Encoding;
 use crate::pb::*;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use bytes::Bytes;
-use image::{DynamicImage, ImageBuffer, ImageOutputFormat};
+use image::{DynamicImage, ImageBuffer, RgbaImage};
 use photon_rs::filters;
-use photon_rs::{multiple, native::open_image_from_bytes, transform, PhotonImage};
-use std::io::Cursor;
+use photon_rs::{native::open_image_from_bytes, transform, PhotonImage};
 use std::ops::{Deref, DerefMut};
-use std::sync::LazyLock;
+use std::sync::Arc;
 
-static WATERMARK: LazyLock<PhotonImage> = LazyLock::new(|| {
-    let data = include_bytes!("../../gopher.png");
-    let watermark = open_image_from_bytes(data).unwrap();
-    transform::resize(&watermark, 64, 64, transform::SamplingFilter::Nearest)
-});
-
-pub struct Photon(PhotonImage);
+pub struct Photon {
+    img: PhotonImage,
+    assets: Arc<Assets>,
+}
 
 impl Deref for Photon {
     type Target = PhotonImage;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.img
     }
 }
 
 impl DerefMut for Photon {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.img
     }
 }
 
@@ -41,47 +38,83 @@ impl TryFrom<Bytes> for Photon {
 
 impl Photon {
     pub fn new(img: PhotonImage) -> Self {
-        Self(img)
+        Self {
+            img,
+            assets: BUILTIN_ASSETS.clone(),
+        }
+    }
+
+    /// Take watermarks and the font from `assets` instead of the built-ins
+    pub fn with_assets(mut self, assets: Arc<Assets>) -> Self {
+        self.assets = assets;
+        self
+    }
+
+    fn to_rgba(&self) -> RgbaImage {
+        ImageBuffer::from_raw(self.get_width(), self.get_height(), self.get_raw_pixels()).unwrap()
+    }
+
+    fn set_rgba(&mut self, img: RgbaImage) {
+        let (width, height) = img.dimensions();
+        self.img = PhotonImage::new(img.into_raw(), width, height);
     }
 }
 
 impl Engine for Photon {
-    fn apply(&mut self, specs: &[Spec]) {
+    fn apply(&mut self, specs: &[Spec]) -> Result<()> {
         for spec in specs.iter() {
+            check(spec)?;
             match spec.data {
                 Some(spec::Data::Resize(ref v)) => self.transform(v),
                 Some(spec::Data::Watermark(ref v)) => self.transform(v),
                 Some(spec::Data::Fliph(ref v)) => self.transform(v),
                 Some(spec::Data::Flipv(ref v)) => self.transform(v),
                 Some(spec::Data::Filter(ref v)) => self.transform(v),
-                _ => unreachable!(),
+                Some(spec::Data::Crop(ref v)) => self.transform(v),
+                Some(spec::Data::Rotate(ref v)) => self.transform(v),
+                Some(spec::Data::Fit(ref v)) => self.transform(v),
+                Some(spec::Data::Smartcrop(ref v)) => self.transform(v),
+                Some(spec::Data::Text(ref v)) => self.transform(v),
+                None => {}
             }
         }
+        Ok(())
     }
 
-    fn generate(self, format: ImageOutputFormat) -> Vec<u8> {
+    fn generate(self, format: Encoding) -> Result<Vec<u8>> {
         image_to_buf(&self, format)
     }
 }
 
 impl SpecTransformer<&Resize> for Photon {
     fn transform(&mut self, op: &Resize) {
-        let img = match resize::ResizeType::from_i32(op.rtype).unwrap() {
-            resize::ResizeType::Normal => transform::resize(
-                self,
-                op.width,
-                op.height,
-                resize::SampleFilter::from_i32(op.filter).unwrap().into(),
-            ),
+        let img = match op.rtype() {
+            resize::ResizeType::Normal => {
+                transform::resize(self, op.width, op.height, op.filter().into())
+            }
             resize::ResizeType::SeamCarve => transform::seam_carve(self, op.width, op.height),
         };
-        self.0 = img;
+        self.img = img;
     }
 }
 
 impl SpecTransformer<&Watermark> for Photon {
     fn transform(&mut self, op: &Watermark) {
-        multiple::watermark(self, &WATERMARK, op.x, op.y);
+        let assets = self.assets.clone();
+        if let Some(mark) = assets.watermark(&op.name) {
+            let mut img = self.to_rgba();
+            ops::watermark(&mut img, mark, op);
+            self.set_rgba(img);
+        }
+    }
+}
+
+impl SpecTransformer<&TextOverlay> for Photon {
+    fn transform(&mut self, op: &TextOverlay) {
+        let assets = self.assets.clone();
+        let mut img = self.to_rgba();
+        ops::text(&mut img, assets.font(), op);
+        self.set_rgba(img);
     }
 }
 
@@ -103,17 +136,45 @@ impl SpecTransformer<&Filter> for Photon {
     }
 }
 
-fn image_to_buf(img: &PhotonImage, format: ImageOutputFormat) -> Vec<u8> {
+impl SpecTransformer<&Crop> for Photon {
+    fn transform(&mut self, op: &Crop) {
+        if let Some(img) = ops::crop(&self.to_rgba(), op) {
+            self.set_rgba(img);
+        }
+    }
+}
+
+impl SpecTransformer<&Rotate> for Photon {
+    fn transform(&mut self, op: &Rotate) {
+        let img = ops::rotate(&self.to_rgba(), op.degrees);
+        self.set_rgba(img);
+    }
+}
+
+impl SpecTransformer<&Fit> for Photon {
+    fn transform(&mut self, op: &Fit) {
+        if let Some(img) = ops::fit(&self.to_rgba(), op) {
+            self.set_rgba(img);
+        }
+    }
+}
+
+impl SpecTransformer<&SmartCrop> for Photon {
+    fn transform(&mut self, op: &SmartCrop) {
+        if let Some(img) = ops::smart_crop(&self.to_rgba(), op) {
+            self.set_rgba(img);
+        }
+    }
+}
+
+fn image_to_buf(img: &PhotonImage, format: Encoding) -> Result<Vec<u8>> {
     let raw_pixels = img.get_raw_pixels();
     let width = img.get_width();
     let height = img.get_height();
 
-    let img_buffer = ImageBuffer::from_vec(width, height, raw_pixels).unwrap();
+    let img_buffer = ImageBuffer::from_vec(width, height, raw_pixels)
+        .ok_or_else(|| anyhow!("{}x{} does not match the pixel buffer", width, height))?;
     let dynimage = DynamicImage::ImageRgba8(img_buffer);
 
-    let vec = Vec::with_capacity(32767);
-    let mut buffer = Cursor::new(vec);
-
-    dynimage.write_to(&mut buffer, format).unwrap();
-    buffer.into_inner()
+    Ok(format.encode(&dynimage)?)
 }
@@ -0,0 +1,172 @@
+use crate::cache::CacheKey;
+use crate::source::{FetchError, Sources};
+use anyhow::Result;
+use bytes::Bytes;
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use lru::LruCache;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+const SHARDS: usize = 16;
+
+/// A download in progress, every request for the same url awaits the same one
+type Flight = Shared<BoxFuture<'static, Result<Bytes, Arc<anyhow::Error>>>>;
+
+struct Inner {
+    sources: Sources,
+    shards: Vec<Mutex<LruCache<CacheKey, Bytes>>>,
+    inflight: Mutex<HashMap<CacheKey, Flight>>,
+}
+
+impl Inner {
+    fn shard(&self, key: &CacheKey) -> &Mutex<LruCache<CacheKey, Bytes>> {
+        &self.shards[key.shard(self.shards.len())]
+    }
+
+    fn cached(&self, key: &CacheKey) -> Option<Bytes> {
+        self.shard(key).lock().unwrap().get(key).cloned()
+    }
+}
+
+/// Fetcher caches source images and coalesces concurrent downloads.
+///
+/// Locks are only held for map lookups, never across a download, so a slow
+/// origin only delays the requests that need its images.
+#[derive(Clone)]
+pub struct Fetcher {
+    inner: Arc<Inner>,
+}
+
+impl Fetcher {
+    pub fn new(sources: Sources, entries: NonZeroUsize) -> Self {
+        let per_shard = NonZeroUsize::new(entries.get().div_ceil(SHARDS)).unwrap();
+        let shards = (0..SHARDS)
+            .map(|_| Mutex::new(LruCache::new(per_shard)))
+            .collect();
+        Self {
+            inner: Arc::new(Inner {
+                sources,
+                shards,
+                inflight: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<Bytes, FetchError> {
+        let key = CacheKey::new(url, "", "", "");
+        if let Some(data) = self.inner.cached(&key) {
+            info!("get from cache {}", key.as_str());
+            return Ok(data);
+        }
+
+        let flight = {
+            let mut inflight = self.inner.inflight.lock().unwrap();
+            // the download may have finished since we looked, it fills the
+            // cache before leaving `inflight`
+            if let Some(data) = self.inner.cached(&key) {
+                return Ok(data);
+            }
+            inflight
+                .entry(key.clone())
+                .or_insert_with(|| self.start(url, key))
+                .clone()
+        };
+        flight.await.map_err(|e| FetchError::from(&*e))
+    }
+
+    /// Download on its own task, so it completes even if the request that
+    /// started it goes away
+    fn start(&self, url: &str, key: CacheKey) -> Flight {
+        let inner = self.inner.clone();
+        let url = url.to_string();
+        let handle = tokio::spawn(async move {
+            let res = inner.sources.fetch(&url).await;
+            if let Ok(data) = &res {
+                inner
+                    .shard(&key)
+                    .lock()
+                    .unwrap()
+                    .put(key.clone(), data.clone());
+            }
+            // failures are not cached, the next request tries again
+            inner.inflight.lock().unwrap().remove(&key);
+            res.map_err(Arc::new)
+        });
+
+        async move { handle.await.map_err(|e| Arc::new(anyhow::Error::from(e)))? }
+            .boxed()
+            .shared()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::ImageSource;
+    use axum::http::StatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Answers every url after a delay, failing the first `fail` times with
+    /// a timeout
+    struct SlowSource {
+        calls: Arc<AtomicUsize>,
+        fail: usize,
+    }
+
+    impl ImageSource for SlowSource {
+        fn fetch<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Bytes>> {
+            Box::pin(async move {
+                let call = self.calls.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                if call < self.fail {
+                    return Err(FetchError::Timeout(format!("{} timed out", url)).into());
+                }
+                Ok(Bytes::from(url.to_string()))
+            })
+        }
+    }
+
+    fn fetcher(fail: usize) -> (Fetcher, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let source = SlowSource {
+            calls: calls.clone(),
+            fail,
+        };
+        let sources = Sources::default().register("slow", source);
+        (Fetcher::new(sources, NonZeroUsize::new(64).unwrap()), calls)
+    }
+
+    #[tokio::test]
+    async fn concurrent_fetches_should_share_one_download() {
+        let (fetcher, calls) = fetcher(0);
+
+        let tasks: Vec<_> = (0..10)
+            .map(|_| {
+                let fetcher = fetcher.clone();
+                tokio::spawn(async move { fetcher.fetch("slow://a").await.unwrap() })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), "slow://a");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        fetcher.fetch("slow://a").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        fetcher.fetch("slow://b").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_fetches_should_not_be_cached() {
+        let (fetcher, calls) = fetcher(1);
+
+        let err = fetcher.fetch("slow://a").await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(fetcher.fetch("slow://a").await.unwrap(), "slow://a");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::pb::{ImageFormat, ImageSpec};
+use accept_header::Accept;
+use axum::http::header::ACCEPT;
+use axum::http::HeaderMap;
+#[cfg(feature = "avif")]
+use image::codecs::avif::AvifEncoder;
+use image::{DynamicImage, ImageOutputFormat, ImageResult};
+use mime::Mime;
+use std::io::Cursor;
+use tracing::debug;
+
+const DEFAULT_QUALITY: u8 = 85;
+/// 1 is the slowest and smallest, 10 the fastest. 4 is the encoder default
+#[cfg(feature = "avif")]
+const AVIF_SPEED: u8 = 4;
+
+/// Formats we can encode, in order of preference when the client has none
+const AVAILABLE: &[(&str, ImageFormat)] = &[
+    ("image/png", ImageFormat::Png),
+    ("image/apng", ImageFormat::Png),
+    ("image/jpeg", ImageFormat::Jpeg),
+    ("image/webp", ImageFormat::Webp),
+    #[cfg(feature = "avif")]
+    ("image/avif", ImageFormat::Avif),
+    ("image/gif", ImageFormat::Gif),
+];
+
+/// Encoder settings of an output image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Png,
+    Jpeg(u8),
+    /// Always lossless, the encoder has no quality setting
+    WebP,
+    Gif,
+    #[cfg(feature = "avif")]
+    Avif(u8),
+}
+
+impl Encoding {
+    pub fn encode(self, img: &DynamicImage) -> ImageResult<Vec<u8>> {
+        let mut buf = Cursor::new(Vec::with_capacity(32767));
+        let format = match self {
+            #[cfg(feature = "avif")]
+            Encoding::Avif(quality) => {
+                let encoder = AvifEncoder::new_with_speed_quality(&mut buf, AVIF_SPEED, quality);
+                img.write_with_encoder(encoder)?;
+                return Ok(buf.into_inner());
+            }
+            Encoding::Png => ImageOutputFormat::Png,
+            Encoding::Jpeg(quality) => ImageOutputFormat::Jpeg(quality),
+            Encoding::WebP => ImageOutputFormat::WebP,
+            Encoding::Gif => ImageOutputFormat::Gif,
+        };
+        img.write_to(&mut buf, format)?;
+        Ok(buf.into_inner())
+    }
+}
+
+/// OutputFormat is the encoder to use and the mime type it produces
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputFormat {
+    pub mime: &'static str,
+    pub format: Encoding,
+    /// whether the format came from the Accept header
+    pub negotiated: bool,
+}
+
+impl OutputFormat {
+    /// The format the spec asks for, or the best one the client accepts
+    pub fn choose(spec: &ImageSpec, headers: &HeaderMap) -> Self {
+        match spec.format() {
+            ImageFormat::Auto => Self {
+                negotiated: true,
+                ..Self::new(negotiate(headers), spec.quality)
+            },
+            format => Self::new(format, spec.quality),
+        }
+    }
+
+    /// `quality` applies to jpeg and avif, webp is lossless and png and gif
+    /// have no such setting. Avif turns into png without the avif feature
+    pub fn new(format: ImageFormat, quality: u32) -> Self {
+        let quality = match quality {
+            0 => DEFAULT_QUALITY,
+            q => q.min(100) as u8,
+        };
+        let (mime, format) = match format {
+            ImageFormat::Jpeg => ("image/jpeg", Encoding::Jpeg(quality)),
+            ImageFormat::Webp => ("image/webp", Encoding::WebP),
+            #[cfg(feature = "avif")]
+            ImageFormat::Avif => ("image/avif", Encoding::Avif(quality)),
+            ImageFormat::Gif => ("image/gif", Encoding::Gif),
+            #[cfg(not(feature = "avif"))]
+            ImageFormat::Avif => ("image/png", Encoding::Png),
+            ImageFormat::Png | ImageFormat::Auto => ("image/png", Encoding::Png),
+        };
+        Self {
+            mime,
+            format,
+            negotiated: false,
+        }
+    }
+
+    /// Distinguishes outputs of the same image in the result cache
+    pub fn cache_key(&self) -> String {
+        format!("{}:{:?}", self.mime, self.format)
+    }
+}
+
+/// Png when there is no Accept header, or it can't be parsed or satisfied
+fn negotiate(headers: &HeaderMap) -> ImageFormat {
+    let Some(accept) = headers.get(ACCEPT) else {
+        return ImageFormat::Png;
+    };
+    let accept: Accept = match String::from_utf8_lossy(accept.as_bytes()).parse() {
+        Ok(accept) => accept,
+        Err(_) => {
+            debug!("ignore malformed accept header {:?}", accept);
+            return ImageFormat::Png;
+        }
+    };
+
+    let available: Vec<Mime> = AVAILABLE
+        .iter()
+        .map(|(mime, _)| mime.parse().unwrap())
+        .collect();
+    let Ok(negotiated) = accept.negotiate(&available) else {
+        debug!("no acceptable format in {:?}", accept);
+        return ImageFormat::Png;
+    };
+
+    AVAILABLE
+        .iter()
+        .find(|(mime, _)| *mime == negotiated.essence_str())
+        .map(|(_, format)| *format)
+        .unwrap_or(ImageFormat::Png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn negotiate_should_pick_accepted_format() {
+        assert_eq!(negotiate(&accept("image/webp")), ImageFormat::Webp);
+        #[cfg(feature = "avif")]
+        assert_eq!(negotiate(&accept("image/avif")), ImageFormat::Avif);
+        #[cfg(not(feature = "avif"))]
+        assert_eq!(negotiate(&accept("image/avif")), ImageFormat::Png);
+        assert_eq!(negotiate(&accept("image/gif")), ImageFormat::Gif);
+        assert_eq!(negotiate(&accept("image/apng")), ImageFormat::Png);
+        assert_eq!(
+            negotiate(&accept("image/jpeg;q=0.9, image/webp;q=0.5")),
+            ImageFormat::Jpeg
+        );
+    }
+
+    #[test]
+    fn negotiate_should_fall_back_to_png() {
+        assert_eq!(negotiate(&HeaderMap::new()), ImageFormat::Png);
+        assert_eq!(negotiate(&accept("text/html")), ImageFormat::Png);
+        assert_eq!(negotiate(&accept("not a mime;;q=x")), ImageFormat::Png);
+    }
+
+    #[test]
+    fn spec_format_should_override_accept() {
+        let spec = ImageSpec::new(vec![]).with_format(ImageFormat::Jpeg, 60);
+        let format = OutputFormat::choose(&spec, &accept("image/webp"));
+        assert_eq!(format.mime, "image/jpeg");
+        assert_eq!(format.format, Encoding::Jpeg(60));
+        assert!(!format.negotiated);
+
+        let format = OutputFormat::choose(&ImageSpec::new(vec![]), &accept("image/webp"));
+        assert_eq!(format.mime, "image/webp");
+        assert!(format.negotiated);
+
+        let format = OutputFormat::new(ImageFormat::Jpeg, 1000);
+        assert_eq!(format.format, Encoding::Jpeg(100));
+        let format = OutputFormat::new(ImageFormat::Avif, 0);
+        #[cfg(feature = "avif")]
+        assert_eq!(format.format, Encoding::Avif(DEFAULT_QUALITY));
+        #[cfg(not(feature = "avif"))]
+        assert_eq!((format.mime, format.format), ("image/png", Encoding::Png));
+    }
+
+    #[test]
+    #[cfg(feature = "avif")]
+    fn avif_should_follow_quality() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x ^ y) * 4) as u8])
+        }));
+        let low = Encoding::Avif(10).encode(&img).unwrap();
+        let high = Encoding::Avif(95).encode(&img).unwrap();
+        assert!(low.len() < high.len(), "{} vs {}", low.len(), high.len());
+    }
+}
@@ -1,61 +1,168 @@
-#![feature(lazy_cell)]
-
-use accept_header::Accept;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use axum::extract::State;
+use axum::Json;
 use axum::{
     extract::Path,
-    http::header::ACCEPT,
+    http::header::{ACCEPT, CONTENT_TYPE, VARY},
     http::{HeaderMap, HeaderValue, StatusCode},
     routing::get,
     serve, Router,
 };
 use bytes::Bytes;
-use image::ImageOutputFormat;
-use lru::LruCache;
-use mime::Mime;
 use percent_encoding::percent_decode_str;
 use serde::Deserialize;
-use std::{
-    collections::hash_map::DefaultHasher,
-    convert::TryInto,
-    hash::{Hash, Hasher},
-    num::NonZeroUsize,
-    sync::Arc,
-};
+use std::{convert::TryInto, num::NonZeroUsize, sync::Arc};
 use tokio::net::TcpListener;
-use tokio::sync::Mutex;
 use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
-use tracing::info;
+use tracing::{info, warn};
 
+mod cache;
 mod engine;
+mod fetch;
+mod format;
 mod helper;
 mod pb;
+mod process;
+mod signature;
+mod source;
 
 use pb::*;
 
-use crate::engine::{Engine, Photon};
+use crate::cache::{CacheKey, CacheStats, DiskCache, ResultCache};
+use crate::engine::{Assets, EngineKind};
+use crate::fetch::Fetcher;
+use crate::format::OutputFormat;
+use crate::process::{ProcessLimits, Processor};
+use crate::signature::Signer;
+use crate::source::{FileSource, HttpSource, Limits, Sources};
 
 #[derive(Deserialize)]
 struct Params {
+    signature: String,
     spec: String,
     url: String,
 }
 
-type Cache = Arc<Mutex<LruCache<u64, Bytes>>>;
+#[derive(Clone)]
+struct AppState {
+    fetcher: Fetcher,
+    results: Arc<ResultCache>,
+    signer: Arc<Signer>,
+    processor: Processor,
+}
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt::init();
+impl AppState {
+    fn new(sources: Sources, results: ResultCache, signer: Signer, processor: Processor) -> Self {
+        Self {
+            fetcher: Fetcher::new(sources, NonZeroUsize::new(1024).unwrap()),
+            results: Arc::new(results),
+            signer: Arc::new(signer),
+            processor,
+        }
+    }
+}
+
+/// http(s) is always served, file:// only when THUMBOR_FILE_ROOT is set
+fn sources() -> Result<Sources> {
+    let http = HttpSource::new(Limits::from_env("THUMBOR_HTTP"))?;
+    let mut sources = Sources::default()
+        .register("http", http.clone())
+        .register("https", http);
+
+    if let Ok(root) = std::env::var("THUMBOR_FILE_ROOT") {
+        let file = FileSource::new(root, Limits::from_env("THUMBOR_FILE"))?;
+        sources = sources.register("file", file);
+    }
+    Ok(sources)
+}
+
+/// Processed images stay in memory, and on disk when THUMBOR_CACHE_DIR is set
+fn results() -> Result<ResultCache> {
+    let disk = match std::env::var("THUMBOR_CACHE_DIR") {
+        Ok(dir) => {
+            let max_size = std::env::var("THUMBOR_CACHE_MAX_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1024 * 1024 * 1024);
+            Some(DiskCache::open(dir, max_size)?)
+        }
+        Err(_) => None,
+    };
+    Ok(ResultCache::new(NonZeroUsize::new(1024).unwrap(), disk))
+}
+
+/// Urls are signed with THUMBOR_SECRET, THUMBOR_ALLOW_UNSAFE=1 also serves
+/// `/image/unsafe/...` and is meant for development
+fn signer() -> Result<Signer> {
+    let secret = std::env::var("THUMBOR_SECRET").ok();
+    let allow_unsafe = std::env::var("THUMBOR_ALLOW_UNSAFE").is_ok_and(|v| v == "1");
+    if secret.is_none() && !allow_unsafe {
+        anyhow::bail!("set THUMBOR_SECRET, or THUMBOR_ALLOW_UNSAFE=1 for development");
+    }
+    Ok(Signer::new(
+        secret.as_deref().map(str::as_bytes),
+        allow_unsafe,
+    ))
+}
+
+/// Watermarks beyond the built-in gopher come from THUMBOR_WATERMARK_DIR
+fn assets() -> Result<Arc<Assets>> {
+    let assets = match std::env::var("THUMBOR_WATERMARK_DIR") {
+        Ok(dir) => Assets::load(dir)?,
+        Err(_) => Assets::builtin(),
+    };
+    Ok(Arc::new(assets))
+}
 
-    let cache: Cache = Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(1024).unwrap())));
+/// THUMBOR_ENGINE picks `photon` (the default) or `image` to process images
+fn engine() -> Result<EngineKind> {
+    match std::env::var("THUMBOR_ENGINE") {
+        Ok(engine) => engine.parse(),
+        Err(_) => Ok(EngineKind::default()),
+    }
+}
 
-    let app = Router::new()
+fn app(state: AppState) -> Router {
+    Router::new()
         .route("/", get(root))
-        .route("/image/:spec/:url", get(generate))
+        .route("/image/:signature/:spec/:url", get(generate))
+        .route("/stats", get(stats))
         .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()))
-        .with_state(cache);
+        .with_state(state)
+}
+
+/// Path that serves `url` processed by the encoded `spec`, signed with `secret`
+fn sign(secret: &str, spec: &str, url: &str) -> Result<String> {
+    let spec: ImageSpec = spec.try_into().context("invalid spec")?;
+    let signer = Signer::new(Some(secret.as_bytes()), false);
+    Ok(signer
+        .signed_path(&spec, url)
+        .expect("the signer has a secret"))
+}
+
+#[tokio::main]
+async fn main() {
+    // `thumbor sign <spec> <url>` prints a signed path instead of serving
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [cmd, spec, url] = &args[..] {
+        if cmd == "sign" {
+            let secret = std::env::var("THUMBOR_SECRET")
+                .context("THUMBOR_SECRET is not set")
+                .unwrap();
+            println!("{}", sign(&secret, spec, url).unwrap());
+            return;
+        }
+    }
+
+    tracing_subscriber::fmt::init();
+
+    let app = app(AppState::new(
+        sources().unwrap(),
+        results().unwrap(),
+        signer().unwrap(),
+        Processor::new(ProcessLimits::from_env(), assets().unwrap()).with_engine(engine().unwrap()),
+    ));
 
     let listener = TcpListener::bind("127.0.0.1:5001").await.unwrap();
     info!("Starting thumbor server on 127.0.0.1:5001");
@@ -71,106 +178,91 @@ async fn root() -> &'static str {
     "Thumbor Server"
 }
 
-struct OutputFormat(ImageOutputFormat);
-
-impl From<Mime> for OutputFormat {
-    fn from(value: Mime) -> Self {
-        match value.to_string().as_str() {
-            "image/png" => OutputFormat(ImageOutputFormat::Png),
-            "image/apng" => OutputFormat(ImageOutputFormat::Png),
-            "image/jpeg" => OutputFormat(ImageOutputFormat::Jpeg(85)),
-            "image/webp" => OutputFormat(ImageOutputFormat::WebP),
-            _ => OutputFormat(ImageOutputFormat::Jpeg(85)),
-        }
-    }
-}
-
-fn get_format(headers: HeaderMap) -> OutputFormat {
-    let accept_header = headers.get(ACCEPT).map(|v| v.as_bytes());
-    let mut format = OutputFormat(ImageOutputFormat::Png);
-    if let Some(accept_header) = accept_header {
-        let str_accept = String::from_utf8_lossy(accept_header);
-        let accept: Accept = str_accept.parse().unwrap();
-        println!("accepts {:?}", accept);
-
-        let available: Vec<Mime> = vec![
-            "image/png".parse().unwrap(),
-            "image/apng".parse().unwrap(),
-            "image/jpeg".parse().unwrap(),
-            "image/webp".parse().unwrap(),
-        ];
-
-        let negotiated = accept.negotiate(&available).unwrap();
-        println!("negotiated: {}", negotiated);
-
-        format = negotiated.into();
-    }
-    format
+async fn stats(State(state): State<AppState>) -> Json<CacheStats> {
+    Json(state.results.stats())
 }
 
 async fn generate(
-    Path(Params { spec, url }): Path<Params>,
-    State(cache): State<Cache>,
+    Path(Params {
+        signature,
+        spec,
+        url,
+    }): Path<Params>,
+    State(state): State<AppState>,
     headers: HeaderMap,
-) -> Result<(HeaderMap, Vec<u8>), StatusCode> {
+) -> Result<(HeaderMap, Bytes), StatusCode> {
     let url = percent_decode_str(&url).decode_utf8_lossy();
-    let spec: ImageSpec = spec
+    if !state.signer.verify(&signature, &spec, &url) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let image_spec: ImageSpec = spec
         .as_str()
         .try_into()
         .map_err(|_| StatusCode::BAD_REQUEST)?;
 
-    let img = retrieve_image(&url, cache)
-        .await
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    let mut engine: Photon = img
-        .try_into()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    engine.apply(&spec.specs);
-
-    let format = get_format(headers);
-    println!("output format: {:?}", format.0);
-    let img = engine.generate(format.0);
-
-    info!("done, image size {}", img.len());
+    let format = OutputFormat::choose(&image_spec, &headers);
 
     let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(format.mime));
+    if format.negotiated {
+        headers.insert(VARY, HeaderValue::from_static(ACCEPT.as_str()));
+    }
 
-    headers.insert("content-type", HeaderValue::from_static("image/jpeg"));
-    Ok((headers, img.to_vec()))
-}
+    let key = CacheKey::new(
+        &url,
+        &spec,
+        &format.cache_key(),
+        state.processor.engine().as_str(),
+    );
+    if let Some(img) = state.results.get(&key).await {
+        info!("get result from cache {}", key.as_str());
+        return Ok((headers, img));
+    }
 
-async fn retrieve_image(url: &str, cache: Cache) -> Result<Bytes> {
-    let mut hasher = DefaultHasher::new();
-    url.hash(&mut hasher);
+    let img = state.fetcher.fetch(&url).await.map_err(|e| {
+        warn!("failed to fetch {}: {}", url, e);
+        e.status()
+    })?;
 
-    let key = hasher.finish();
+    let img = state
+        .processor
+        .process(img, image_spec.specs, format.format)
+        .await
+        .map_err(|e| {
+            warn!("failed to process {}: {}", url, e);
+            e.status()
+        })?;
 
-    let g = &mut cache.lock().await;
-    let data = match g.get(&key) {
-        Some(v) => {
-            info!("get from cache {}", key);
-            v.to_owned()
-        }
-        None => {
-            info!("send request...");
-            let resp = reqwest::get(url).await?;
-            let data = resp.bytes().await?;
-            g.put(key, data.clone());
-            data
-        }
-    };
-    info!("get data success");
-    Ok(data)
+    info!("done, image size {}", img.len());
+
+    state.results.put(key, img.clone()).await;
+    Ok((headers, img))
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::cache::{CacheStats, ResultCache};
+    use crate::engine::BUILTIN_ASSETS;
     use crate::helper::TestClient;
-    use crate::root;
+    use crate::pb::{ImageFormat, ImageSpec, Spec};
+    use crate::process::{ProcessLimits, Processor};
+    use crate::signature::Signer;
+    use crate::source::{FileSource, Limits, MemorySource, Sources};
+    use crate::{app, root, sign, AppState};
     use axum::routing::get;
     use axum::Router;
     use http::StatusCode;
+    use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+    use std::num::NonZeroUsize;
+
+    fn state(sources: Sources) -> AppState {
+        AppState::new(
+            sources,
+            ResultCache::new(NonZeroUsize::new(16).unwrap(), None),
+            Signer::new(None, true),
+            Processor::new(ProcessLimits::default(), BUILTIN_ASSETS.clone()),
+        )
+    }
 
     #[tokio::test]
     async fn handler_into_service() {
@@ -182,4 +274,141 @@ mod tests {
         assert_eq!(res.status(), StatusCode::OK);
         assert_eq!(res.text().await, "Thumbor Server");
     }
+
+    #[tokio::test]
+    async fn generate_should_use_source_by_scheme() {
+        let images = MemorySource::default()
+            .insert("mem://gopher.png", &include_bytes!("../gopher.png")[..])
+            .insert("mem://notes.txt", "not an image");
+        let client = TestClient::new(app(state(Sources::default().register("mem", images))));
+
+        let spec: String = (&ImageSpec::new(vec![Spec::new_fliph()])).into();
+        let path = |url: &str| {
+            format!(
+                "/image/unsafe/{}/{}",
+                spec,
+                utf8_percent_encode(url, NON_ALPHANUMERIC)
+            )
+        };
+
+        let res = client.get(&path("mem://gopher.png")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.bytes().await.is_empty());
+
+        let res = client.get(&path("mem://missing.png")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = client.get(&path("https://example.com/gopher.png")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = client.get(&path("mem://notes.txt")).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn generate_should_cache_results() {
+        let images = MemorySource::default()
+            .insert("mem://gopher.png", &include_bytes!("../gopher.png")[..]);
+        let client = TestClient::new(app(state(Sources::default().register("mem", images))));
+
+        let spec: String = (&ImageSpec::new(vec![Spec::new_flipv()])).into();
+        let path = format!(
+            "/image/unsafe/{}/{}",
+            spec,
+            utf8_percent_encode("mem://gopher.png", NON_ALPHANUMERIC)
+        );
+
+        let first = client.get(&path).await.bytes().await;
+        let second = client.get(&path).await.bytes().await;
+        assert_eq!(first, second);
+
+        // the same image as webp is a different result
+        let res = client.get(&path).header("accept", "image/webp").await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let stats: CacheStats = client.get("/stats").await.json().await;
+        assert_eq!((stats.memory_hits, stats.misses), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn generate_should_send_negotiated_content_type() {
+        let images = MemorySource::default()
+            .insert("mem://gopher.png", &include_bytes!("../gopher.png")[..]);
+        let client = TestClient::new(app(state(Sources::default().register("mem", images))));
+        let url = utf8_percent_encode("mem://gopher.png", NON_ALPHANUMERIC).to_string();
+
+        let spec: String = (&ImageSpec::new(vec![Spec::new_fliph()])).into();
+        let path = format!("/image/unsafe/{}/{}", spec, url);
+        for (accept, mime) in [
+            ("image/webp", "image/webp"),
+            ("image/gif", "image/gif"),
+            ("text/html", "image/png"),
+            ("garbage;;q=", "image/png"),
+        ] {
+            let res = client.get(&path).header("accept", accept).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()["content-type"], mime);
+            assert_eq!(res.headers()["vary"], "accept");
+        }
+
+        // an explicit format wins and does not depend on accept
+        let spec = ImageSpec::new(vec![Spec::new_fliph()]).with_format(ImageFormat::Jpeg, 70);
+        let spec: String = (&spec).into();
+        let res = client
+            .get(&format!("/image/unsafe/{}/{}", spec, url))
+            .header("accept", "image/webp")
+            .await;
+        assert_eq!(res.headers()["content-type"], "image/jpeg");
+        assert!(res.headers().get("vary").is_none());
+    }
+
+    #[tokio::test]
+    async fn generate_should_reject_oversized_sources() {
+        let limits = Limits {
+            max_size: 16,
+            ..Default::default()
+        };
+        let files = FileSource::new(env!("CARGO_MANIFEST_DIR"), limits).unwrap();
+        let client = TestClient::new(app(state(Sources::default().register("file", files))));
+
+        let spec: String = (&ImageSpec::new(vec![Spec::new_fliph()])).into();
+        let url = utf8_percent_encode("file:///gopher.png", NON_ALPHANUMERIC);
+        let res = client.get(&format!("/image/unsafe/{}/{}", spec, url)).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn generate_should_check_signature() {
+        let images = MemorySource::default()
+            .insert("mem://gopher.png", &include_bytes!("../gopher.png")[..]);
+        let signer = Signer::new(Some(b"secret"), false);
+        let path = signer
+            .signed_path(&ImageSpec::new(vec![Spec::new_fliph()]), "mem://gopher.png")
+            .unwrap();
+        let client = TestClient::new(app(AppState::new(
+            Sources::default().register("mem", images),
+            ResultCache::new(NonZeroUsize::new(16).unwrap(), None),
+            signer,
+            Processor::new(ProcessLimits::default(), BUILTIN_ASSETS.clone()),
+        )));
+
+        let res = client.get(&path).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // `thumbor sign` produces the same path
+        let spec: String = (&ImageSpec::new(vec![Spec::new_fliph()])).into();
+        assert_eq!(sign("secret", &spec, "mem://gopher.png").unwrap(), path);
+        assert!(sign("secret", "not a spec!", "mem://gopher.png").is_err());
+
+        // a different spec under the same signature
+        let spec: String = (&ImageSpec::new(vec![Spec::new_flipv()])).into();
+        let mut parts: Vec<_> = path.split('/').collect();
+        parts[3] = &spec;
+        let res = client.get(&parts.join("/")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        parts[2] = "unsafe";
+        let res = client.get(&parts.join("/")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
 }
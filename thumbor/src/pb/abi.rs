@@ -3,6 +3,13 @@
 pub struct ImageSpec {
     #[prost(message, repeated, tag = "1")]
     pub specs: ::prost::alloc::vec::Vec<Spec>,
+    /// AUTO picks a format from the Accept header
+    #[prost(enumeration = "ImageFormat", tag = "2")]
+    pub format: i32,
+    /// 1-100, used by jpeg and avif, 0 means the default. webp is always
+    /// lossless
+    #[prost(uint32, tag = "3")]
+    pub quality: u32,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -86,10 +93,79 @@ pub mod resize {
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Watermark {
+    /// margin from the anchor, in pixels
     #[prost(uint32, tag = "1")]
     pub x: u32,
     #[prost(uint32, tag = "2")]
     pub y: u32,
+    /// a watermark from the configured directory, empty is the built-in gopher
+    #[prost(string, tag = "3")]
+    pub name: ::prost::alloc::string::String,
+    /// width relative to the image up to 1, 0 keeps the watermark's own size
+    #[prost(float, tag = "4")]
+    pub scale: f32,
+    /// 0 (transparent) to 1, unset means opaque
+    #[prost(float, optional, tag = "5")]
+    pub opacity: ::core::option::Option<f32>,
+    #[prost(enumeration = "watermark::Anchor", tag = "6")]
+    pub anchor: i32,
+}
+/// Nested message and enum types in `Watermark`.
+pub mod watermark {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Anchor {
+        TopLeft = 0,
+        TopRight = 1,
+        BottomLeft = 2,
+        BottomRight = 3,
+        Center = 4,
+    }
+    impl Anchor {
+        /// String value of the enum field names used in the ProtoBuf definition.
+        ///
+        /// The values are not transformed in any way and thus are considered stable
+        /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                Anchor::TopLeft => "TOP_LEFT",
+                Anchor::TopRight => "TOP_RIGHT",
+                Anchor::BottomLeft => "BOTTOM_LEFT",
+                Anchor::BottomRight => "BOTTOM_RIGHT",
+                Anchor::Center => "CENTER",
+            }
+        }
+        /// Creates an enum from field names used in the ProtoBuf definition.
+        pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+            match value {
+                "TOP_LEFT" => Some(Self::TopLeft),
+                "TOP_RIGHT" => Some(Self::TopRight),
+                "BOTTOM_LEFT" => Some(Self::BottomLeft),
+                "BOTTOM_RIGHT" => Some(Self::BottomRight),
+                "CENTER" => Some(Self::Center),
+                _ => None,
+            }
+        }
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TextOverlay {
+    #[prost(string, tag = "1")]
+    pub text: ::prost::alloc::string::String,
+    /// font size in pixels up to 1000, 0 means 24
+    #[prost(float, tag = "2")]
+    pub size: f32,
+    /// 0xRRGGBBAA, 0 means opaque white
+    #[prost(uint32, tag = "3")]
+    pub color: u32,
+    #[prost(enumeration = "watermark::Anchor", tag = "4")]
+    pub anchor: i32,
+    /// margin from the anchor, in pixels
+    #[prost(uint32, tag = "5")]
+    pub x: u32,
+    #[prost(uint32, tag = "6")]
+    pub y: u32,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -140,8 +216,76 @@ pub mod filter {
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Crop {
+    #[prost(uint32, tag = "1")]
+    pub x: u32,
+    #[prost(uint32, tag = "2")]
+    pub y: u32,
+    #[prost(uint32, tag = "3")]
+    pub width: u32,
+    #[prost(uint32, tag = "4")]
+    pub height: u32,
+}
+/// clockwise, multiples of 90 are lossless
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Rotate {
+    #[prost(float, tag = "1")]
+    pub degrees: f32,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Fit {
+    #[prost(uint32, tag = "1")]
+    pub width: u32,
+    #[prost(uint32, tag = "2")]
+    pub height: u32,
+    #[prost(enumeration = "fit::FitMode", tag = "3")]
+    pub mode: i32,
+    #[prost(enumeration = "Gravity", tag = "4")]
+    pub gravity: i32,
+}
+/// Nested message and enum types in `Fit`.
+pub mod fit {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum FitMode {
+        Cover = 0,
+        Contain = 1,
+    }
+    impl FitMode {
+        /// String value of the enum field names used in the ProtoBuf definition.
+        ///
+        /// The values are not transformed in any way and thus are considered stable
+        /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                FitMode::Cover => "COVER",
+                FitMode::Contain => "CONTAIN",
+            }
+        }
+        /// Creates an enum from field names used in the ProtoBuf definition.
+        pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+            match value {
+                "COVER" => Some(Self::Cover),
+                "CONTAIN" => Some(Self::Contain),
+                _ => None,
+            }
+        }
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SmartCrop {
+    #[prost(uint32, tag = "1")]
+    pub width: u32,
+    #[prost(uint32, tag = "2")]
+    pub height: u32,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Spec {
-    #[prost(oneof = "spec::Data", tags = "1, 2, 3, 4, 5")]
+    #[prost(oneof = "spec::Data", tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10")]
     pub data: ::core::option::Option<spec::Data>,
 }
 /// Nested message and enum types in `Spec`.
@@ -159,5 +303,102 @@ pub mod spec {
         Flipv(super::Flipv),
         #[prost(message, tag = "5")]
         Filter(super::Filter),
+        #[prost(message, tag = "6")]
+        Crop(super::Crop),
+        #[prost(message, tag = "7")]
+        Rotate(super::Rotate),
+        #[prost(message, tag = "8")]
+        Fit(super::Fit),
+        #[prost(message, tag = "9")]
+        Smartcrop(super::SmartCrop),
+        #[prost(message, tag = "10")]
+        Text(super::TextOverlay),
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum ImageFormat {
+    Auto = 0,
+    Jpeg = 1,
+    Png = 2,
+    Webp = 3,
+    /// needs the avif cargo feature, png otherwise
+    Avif = 4,
+    Gif = 5,
+}
+impl ImageFormat {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            ImageFormat::Auto => "AUTO",
+            ImageFormat::Jpeg => "JPEG",
+            ImageFormat::Png => "PNG",
+            ImageFormat::Webp => "WEBP",
+            ImageFormat::Avif => "AVIF",
+            ImageFormat::Gif => "GIF",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "AUTO" => Some(Self::Auto),
+            "JPEG" => Some(Self::Jpeg),
+            "PNG" => Some(Self::Png),
+            "WEBP" => Some(Self::Webp),
+            "AVIF" => Some(Self::Avif),
+            "GIF" => Some(Self::Gif),
+            _ => None,
+        }
+    }
+}
+/// where to anchor an image inside a larger or smaller box
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Gravity {
+    Center = 0,
+    North = 1,
+    NorthEast = 2,
+    East = 3,
+    SouthEast = 4,
+    South = 5,
+    SouthWest = 6,
+    West = 7,
+    NorthWest = 8,
+}
+impl Gravity {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Gravity::Center => "CENTER",
+            Gravity::North => "NORTH",
+            Gravity::NorthEast => "NORTH_EAST",
+            Gravity::East => "EAST",
+            Gravity::SouthEast => "SOUTH_EAST",
+            Gravity::South => "SOUTH",
+            Gravity::SouthWest => "SOUTH_WEST",
+            Gravity::West => "WEST",
+            Gravity::NorthWest => "NORTH_WEST",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "CENTER" => Some(Self::Center),
+            "NORTH" => Some(Self::North),
+            "NORTH_EAST" => Some(Self::NorthEast),
+            "EAST" => Some(Self::East),
+            "SOUTH_EAST" => Some(Self::SouthEast),
+            "SOUTH" => Some(Self::South),
+            "SOUTH_WEST" => Some(Self::SouthWest),
+            "WEST" => Some(Self::West),
+            "NORTH_WEST" => Some(Self::NorthWest),
+            _ => None,
+        }
     }
 }
@@ -1,5 +1,6 @@
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine;
+use image::imageops::FilterType;
 use photon_rs::transform::SamplingFilter;
 use prost::Message;
 
@@ -9,7 +10,17 @@ pub use abi::*;
 
 impl ImageSpec {
     pub fn new(specs: Vec<Spec>) -> Self {
-        Self { specs }
+        Self {
+            specs,
+            ..Default::default()
+        }
+    }
+
+    /// Always output `format` instead of negotiating, `quality` 0 keeps the default
+    pub fn with_format(mut self, format: ImageFormat, quality: u32) -> Self {
+        self.format = format as i32;
+        self.quality = quality;
+        self
     }
 }
 
@@ -42,6 +53,19 @@ impl From<resize::SampleFilter> for SamplingFilter {
     }
 }
 
+impl From<resize::SampleFilter> for FilterType {
+    fn from(v: resize::SampleFilter) -> Self {
+        match v {
+            resize::SampleFilter::Unknown => FilterType::Nearest,
+            resize::SampleFilter::Nearest => FilterType::Nearest,
+            resize::SampleFilter::Triangle => FilterType::Triangle,
+            resize::SampleFilter::CatmullRom => FilterType::CatmullRom,
+            resize::SampleFilter::Gaussian => FilterType::Gaussian,
+            resize::SampleFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
 impl Spec {
     pub fn new_resize_seam_carve(width: u32, height: u32) -> Self {
         Self {
@@ -67,7 +91,51 @@ impl Spec {
 
     pub fn new_watermark(x: u32, y: u32) -> Self {
         Self {
-            data: Some(spec::Data::Watermark(Watermark { x, y })),
+            data: Some(spec::Data::Watermark(Watermark {
+                x,
+                y,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// A watermark from the configured directory, `margin` pixels from `anchor`
+    pub fn new_named_watermark(
+        name: &str,
+        anchor: watermark::Anchor,
+        margin: u32,
+        scale: f32,
+        opacity: f32,
+    ) -> Self {
+        Self {
+            data: Some(spec::Data::Watermark(Watermark {
+                x: margin,
+                y: margin,
+                name: name.to_string(),
+                scale,
+                opacity: Some(opacity),
+                anchor: anchor as i32,
+            })),
+        }
+    }
+
+    /// Text in the bundled font, `color` is 0xRRGGBBAA
+    pub fn new_text(
+        text: &str,
+        size: f32,
+        color: u32,
+        anchor: watermark::Anchor,
+        margin: u32,
+    ) -> Self {
+        Self {
+            data: Some(spec::Data::Text(TextOverlay {
+                text: text.to_string(),
+                size,
+                color,
+                anchor: anchor as i32,
+                x: margin,
+                y: margin,
+            })),
         }
     }
 
@@ -81,6 +149,42 @@ impl Spec {
             data: Some(spec::Data::Fliph(Fliph {})),
         }
     }
+
+    pub fn new_crop(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            data: Some(spec::Data::Crop(Crop {
+                x,
+                y,
+                width,
+                height,
+            })),
+        }
+    }
+
+    /// Rotate clockwise by any angle, multiples of 90 are lossless
+    pub fn new_rotate(degrees: f32) -> Self {
+        Self {
+            data: Some(spec::Data::Rotate(Rotate { degrees })),
+        }
+    }
+
+    pub fn new_fit(width: u32, height: u32, mode: fit::FitMode, gravity: Gravity) -> Self {
+        Self {
+            data: Some(spec::Data::Fit(Fit {
+                width,
+                height,
+                mode: mode as i32,
+                gravity: gravity as i32,
+            })),
+        }
+    }
+
+    pub fn new_smart_crop(width: u32, height: u32) -> Self {
+        Self {
+            data: Some(spec::Data::Smartcrop(SmartCrop { width, height })),
+        }
+    }
+
     pub fn new_filter(f: filter::Filter) -> Self {
         let mut filter = f;
         if filter == filter::Filter::Unknown {
@@ -105,12 +209,24 @@ mod tests {
         let spec2 = Spec::new_watermark(0, 0);
         let spec3 = Spec::new_fliph();
         let spec4 = Spec::new_filter(filter::Filter::Twenties);
-        let image_spec = ImageSpec::new(vec![spec1, spec3, spec4, spec2]);
+        let spec5 = Spec::new_crop(10, 20, 300, 200);
+        let spec6 = Spec::new_rotate(90.0);
+        let spec7 = Spec::new_fit(128, 128, fit::FitMode::Contain, Gravity::SouthEast);
+        let spec8 = Spec::new_smart_crop(64, 64);
+        let spec9 = Spec::new_named_watermark("acme", watermark::Anchor::BottomRight, 8, 0.2, 0.5);
+        let spec10 = Spec::new_text("hello", 32.0, 0xffffffff, watermark::Anchor::TopLeft, 4);
+        let image_spec = ImageSpec::new(vec![
+            spec1, spec3, spec4, spec2, spec5, spec6, spec7, spec8, spec9, spec10,
+        ]);
         let s: String = image_spec.borrow().into();
         println!("spec string: {}", s);
         assert_eq!(image_spec, s.as_str().try_into().unwrap());
 
         let image_spec2 = ImageSpec::try_from(s.as_str()).unwrap();
         assert_eq!(image_spec, image_spec2);
+
+        let image_spec = image_spec.with_format(ImageFormat::Webp, 70);
+        let s: String = image_spec.borrow().into();
+        assert_eq!(image_spec, s.as_str().try_into().unwrap());
     }
 }
@@ -0,0 +1,395 @@
+use crate::engine::{rotated_size, Assets, EngineKind};
+use crate::format::Encoding;
+use crate::pb::*;
+use axum::http::StatusCode;
+use bytes::Bytes;
+use image::io::Reader;
+use std::fmt;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessLimits {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pixels: u64,
+    pub max_output_size: usize,
+    pub timeout: Duration,
+    /// images processed at the same time, more wait for a turn
+    pub concurrency: usize,
+}
+
+impl Default for ProcessLimits {
+    fn default() -> Self {
+        Self {
+            max_width: 8192,
+            max_height: 8192,
+            max_pixels: 40_000_000,
+            max_output_size: 20 * 1024 * 1024,
+            timeout: Duration::from_secs(30),
+            concurrency: std::thread::available_parallelism().map_or(4, |n| n.get()),
+        }
+    }
+}
+
+impl ProcessLimits {
+    /// Read `THUMBOR_MAX_WIDTH`, `THUMBOR_MAX_HEIGHT`, `THUMBOR_MAX_PIXELS`,
+    /// `THUMBOR_MAX_OUTPUT_SIZE`, `THUMBOR_PROCESS_TIMEOUT_SECS` and
+    /// `THUMBOR_PROCESS_CONCURRENCY`, falling back to the defaults
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok()?.parse().ok()
+        }
+        let default = Self::default();
+        Self {
+            max_width: var("THUMBOR_MAX_WIDTH").unwrap_or(default.max_width),
+            max_height: var("THUMBOR_MAX_HEIGHT").unwrap_or(default.max_height),
+            max_pixels: var("THUMBOR_MAX_PIXELS").unwrap_or(default.max_pixels),
+            max_output_size: var("THUMBOR_MAX_OUTPUT_SIZE").unwrap_or(default.max_output_size),
+            timeout: var("THUMBOR_PROCESS_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.timeout),
+            concurrency: var("THUMBOR_PROCESS_CONCURRENCY").unwrap_or(default.concurrency),
+        }
+    }
+
+    fn check(&self, (width, height): (u32, u32)) -> Result<(), String> {
+        if width > self.max_width || height > self.max_height {
+            return Err(format!(
+                "{}x{} is larger than {}x{}",
+                width, height, self.max_width, self.max_height
+            ));
+        }
+        if width as u64 * height as u64 > self.max_pixels {
+            return Err(format!(
+                "{}x{} has more than {} pixels",
+                width, height, self.max_pixels
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ProcessError {
+    /// the source image or the result is over the limits
+    TooLarge(String),
+    /// the image can't be decoded or encoded, or the spec asks for too much
+    Unprocessable(String),
+    Timeout,
+    /// processing panicked
+    Internal(String),
+}
+
+impl ProcessError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ProcessError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ProcessError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ProcessError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ProcessError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessError::TooLarge(msg) => write!(f, "too large: {}", msg),
+            ProcessError::Unprocessable(msg) => write!(f, "unprocessable: {}", msg),
+            ProcessError::Timeout => f.write_str("processing timed out"),
+            ProcessError::Internal(msg) => write!(f, "internal error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ProcessError {}
+
+/// Processor decodes, transforms and encodes images on the blocking pool,
+/// a few at a time, so the async workers stay free to serve requests
+#[derive(Clone)]
+pub struct Processor {
+    limits: ProcessLimits,
+    engine: EngineKind,
+    assets: Arc<Assets>,
+    permits: Arc<Semaphore>,
+}
+
+impl Processor {
+    pub fn new(limits: ProcessLimits, assets: Arc<Assets>) -> Self {
+        Self {
+            limits,
+            engine: EngineKind::default(),
+            assets,
+            permits: Arc::new(Semaphore::new(limits.concurrency.max(1))),
+        }
+    }
+
+    pub fn with_engine(mut self, engine: EngineKind) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    pub fn engine(&self) -> EngineKind {
+        self.engine
+    }
+
+    /// Apply `specs` to the image in `data` and encode it as `format`.
+    ///
+    /// The timeout includes waiting for a turn. A blocking task can't be
+    /// cancelled, one that times out keeps its permit until it finishes, so
+    /// stuck images still count against the concurrency limit.
+    pub async fn process(
+        &self,
+        data: Bytes,
+        specs: Vec<Spec>,
+        format: Encoding,
+    ) -> Result<Bytes, ProcessError> {
+        let limits = self.limits;
+        let engine = self.engine;
+        let assets = self.assets.clone();
+        let work = async {
+            let permit = self
+                .permits
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(|e| ProcessError::Internal(e.to_string()))?;
+            tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                process(&limits, engine, assets, data, &specs, format)
+            })
+            .await
+            .map_err(|e| ProcessError::Internal(e.to_string()))?
+        };
+        timeout(limits.timeout, work)
+            .await
+            .map_err(|_| ProcessError::Timeout)?
+    }
+}
+
+fn process(
+    limits: &ProcessLimits,
+    engine: EngineKind,
+    assets: Arc<Assets>,
+    data: Bytes,
+    specs: &[Spec],
+    format: Encoding,
+) -> Result<Bytes, ProcessError> {
+    // read the size from the header, before decoding allocates the pixels
+    let size = Reader::new(Cursor::new(&data[..]))
+        .with_guessed_format()
+        .map_err(|e| ProcessError::Unprocessable(e.to_string()))?
+        .into_dimensions()
+        .map_err(|e| ProcessError::Unprocessable(e.to_string()))?;
+    limits.check(size).map_err(ProcessError::TooLarge)?;
+
+    assets.check(specs).map_err(ProcessError::Unprocessable)?;
+    let mut size = size;
+    for spec in specs {
+        // watermarks and text are drawn on a canvas of their own first
+        if let Some(canvas) = assets.overlay_size(size, spec) {
+            limits.check(canvas).map_err(ProcessError::Unprocessable)?;
+        }
+        size = output_size(size, spec).map_err(ProcessError::Unprocessable)?;
+        limits.check(size).map_err(ProcessError::Unprocessable)?;
+    }
+
+    let img = engine
+        .process(data, assets, specs, format)
+        .map_err(|e| ProcessError::Unprocessable(e.to_string()))?;
+
+    if img.len() > limits.max_output_size {
+        return Err(ProcessError::TooLarge(format!(
+            "output is {} bytes, more than {}",
+            img.len(),
+            limits.max_output_size
+        )));
+    }
+    Ok(img.into())
+}
+
+/// Size of the image after `spec`, so the limits hold for every step.
+/// Resizing or fitting to nothing is an error
+fn output_size((width, height): (u32, u32), spec: &Spec) -> Result<(u32, u32), String> {
+    let size = match spec.data {
+        Some(spec::Data::Resize(ref v)) if v.width == 0 || v.height == 0 => {
+            return Err(format!("can't resize to {}x{}", v.width, v.height));
+        }
+        Some(spec::Data::Fit(ref v)) if v.width == 0 || v.height == 0 => {
+            return Err(format!("can't fit to {}x{}", v.width, v.height));
+        }
+        Some(spec::Data::Resize(ref v)) => (v.width, v.height),
+        Some(spec::Data::Crop(ref v)) => {
+            let cropped = (
+                v.width.min(width.saturating_sub(v.x)),
+                v.height.min(height.saturating_sub(v.y)),
+            );
+            // an empty crop is skipped
+            if cropped.0 == 0 || cropped.1 == 0 {
+                (width, height)
+            } else {
+                cropped
+            }
+        }
+        Some(spec::Data::Rotate(ref v)) => rotated_size((width, height), v.degrees),
+        Some(spec::Data::Fit(ref v)) => (v.width, v.height),
+        Some(spec::Data::Smartcrop(ref v)) if v.width > 0 && v.height > 0 => (v.width, v.height),
+        _ => (width, height),
+    };
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::BUILTIN_ASSETS;
+
+    const GOPHER: &[u8] = include_bytes!("../gopher.png");
+
+    async fn run(
+        limits: ProcessLimits,
+        data: &'static [u8],
+        specs: Vec<Spec>,
+    ) -> Result<Bytes, ProcessError> {
+        Processor::new(limits, BUILTIN_ASSETS.clone())
+            .process(Bytes::from_static(data), specs, Encoding::Png)
+            .await
+    }
+
+    #[tokio::test]
+    async fn process_should_encode_within_limits() {
+        let img = run(
+            ProcessLimits::default(),
+            GOPHER,
+            vec![Spec::new_crop(0, 0, 100, 50)],
+        )
+        .await
+        .unwrap();
+        let img = image::load_from_memory(&img).unwrap();
+        assert_eq!((img.width(), img.height()), (100, 50));
+    }
+
+    #[tokio::test]
+    async fn process_should_enforce_limits() {
+        let limits = ProcessLimits {
+            max_width: 400,
+            ..Default::default()
+        };
+        let err = run(limits, GOPHER, vec![]).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let limits = ProcessLimits {
+            max_pixels: 200_000,
+            ..Default::default()
+        };
+        let err = run(limits, GOPHER, vec![]).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // the source fits, the results do not
+        let limits = ProcessLimits {
+            max_width: 600,
+            max_height: 600,
+            ..Default::default()
+        };
+        let specs = vec![Spec::new_resize(800, 100, resize::SampleFilter::Nearest)];
+        let err = run(limits, GOPHER, specs).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let err = run(limits, GOPHER, vec![Spec::new_rotate(45.0)])
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // nothing left to encode
+        for spec in [
+            Spec::new_resize(0, 100, resize::SampleFilter::Nearest),
+            Spec::new_fit(100, 0, fit::FitMode::Cover, Gravity::Center),
+        ] {
+            let err = run(ProcessLimits::default(), GOPHER, vec![spec])
+                .await
+                .unwrap_err();
+            assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        // enum values the proto does not define
+        let mut resize = Resize {
+            width: 10,
+            height: 10,
+            rtype: 7,
+            filter: 0,
+        };
+        let mut filter = resize.clone();
+        filter.rtype = 0;
+        filter.filter = 42;
+        for data in [
+            spec::Data::Resize(resize.clone()),
+            spec::Data::Resize(filter),
+            spec::Data::Filter(Filter { filter: -1 }),
+        ] {
+            let spec = Spec { data: Some(data) };
+            let err = run(ProcessLimits::default(), GOPHER, vec![spec])
+                .await
+                .unwrap_err();
+            assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        resize.rtype = 0;
+        let spec = Spec {
+            data: Some(spec::Data::Resize(resize)),
+        };
+        assert!(run(ProcessLimits::default(), GOPHER, vec![spec])
+            .await
+            .is_ok());
+
+        let limits = ProcessLimits {
+            max_output_size: 100,
+            ..Default::default()
+        };
+        let err = run(limits, GOPHER, vec![]).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn process_should_reject_garbage_and_time_out() {
+        let err = run(ProcessLimits::default(), b"not an image", vec![])
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let specs = vec![Spec::new_named_watermark(
+            "missing",
+            watermark::Anchor::TopLeft,
+            0,
+            0.0,
+            0.0,
+        )];
+        let err = run(ProcessLimits::default(), GOPHER, specs)
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // in range, but the text is far wider than the limits
+        let long = "x".repeat(500);
+        let specs = vec![Spec::new_text(
+            &long,
+            500.0,
+            0,
+            watermark::Anchor::TopLeft,
+            0,
+        )];
+        let err = run(ProcessLimits::default(), GOPHER, specs)
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let limits = ProcessLimits {
+            timeout: Duration::ZERO,
+            ..Default::default()
+        };
+        let err = run(limits, GOPHER, vec![]).await.unwrap_err();
+        assert!(matches!(err, ProcessError::Timeout));
+        assert_eq!(err.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::pb::ImageSpec;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signature segment that skips verification, if the server allows it
+pub const UNSAFE: &str = "unsafe";
+
+/// Signer signs and verifies `/image/<signature>/<spec>/<url>` paths.
+///
+/// The signature is an HMAC-SHA256 over `<spec>/<url>`, with the url
+/// percent-decoded, so only holders of the secret can pick what gets fetched
+/// and how it is processed.
+pub struct Signer {
+    secret: Option<Vec<u8>>,
+    allow_unsafe: bool,
+}
+
+impl Signer {
+    pub fn new(secret: Option<&[u8]>, allow_unsafe: bool) -> Self {
+        Self {
+            secret: secret.map(|s| s.to_vec()),
+            allow_unsafe,
+        }
+    }
+
+    fn mac(secret: &[u8], spec: &str, url: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(secret).expect("hmac takes keys of any size");
+        mac.update(spec.as_bytes());
+        mac.update(b"/");
+        mac.update(url.as_bytes());
+        mac
+    }
+
+    pub fn verify(&self, signature: &str, spec: &str, url: &str) -> bool {
+        if signature == UNSAFE {
+            return self.allow_unsafe;
+        }
+        let (Some(secret), Ok(tag)) = (&self.secret, URL_SAFE_NO_PAD.decode(signature)) else {
+            return false;
+        };
+        // verify_slice compares in constant time
+        Self::mac(secret, spec, url).verify_slice(&tag).is_ok()
+    }
+
+    /// Signature for `spec` and `url`, None if no secret is configured
+    pub fn sign(&self, spec: &str, url: &str) -> Option<String> {
+        let secret = self.secret.as_ref()?;
+        let tag = Self::mac(secret, spec, url).finalize().into_bytes();
+        Some(URL_SAFE_NO_PAD.encode(tag))
+    }
+
+    /// The signed path that serves `url` processed by `spec`
+    pub fn signed_path(&self, spec: &ImageSpec, url: &str) -> Option<String> {
+        let spec: String = spec.into();
+        let signature = self.sign(&spec, url)?;
+        Some(format!(
+            "/image/{}/{}/{}",
+            signature,
+            spec,
+            utf8_percent_encode(url, NON_ALPHANUMERIC)
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pb::Spec;
+
+    #[test]
+    fn signature_should_cover_spec_and_url() {
+        let signer = Signer::new(Some(b"secret"), false);
+        let sig = signer.sign("spec", "https://a/b.png").unwrap();
+
+        assert!(signer.verify(&sig, "spec", "https://a/b.png"));
+        assert!(!signer.verify(&sig, "spec2", "https://a/b.png"));
+        assert!(!signer.verify(&sig, "spec", "https://a/c.png"));
+        assert!(!signer.verify("not base64!", "spec", "https://a/b.png"));
+        assert!(!signer.verify(UNSAFE, "spec", "https://a/b.png"));
+
+        let other = Signer::new(Some(b"other"), false);
+        assert!(!other.verify(&sig, "spec", "https://a/b.png"));
+    }
+
+    #[test]
+    fn unsafe_should_need_to_be_allowed() {
+        let signer = Signer::new(None, true);
+        assert!(signer.verify(UNSAFE, "spec", "url"));
+        assert!(signer.sign("spec", "url").is_none());
+        assert!(!signer.verify("c2ln", "spec", "url"));
+    }
+
+    #[test]
+    fn signed_path_should_encode_url() {
+        let signer = Signer::new(Some(b"secret"), false);
+        let spec = ImageSpec::new(vec![Spec::new_fliph()]);
+        let path = signer.signed_path(&spec, "https://a/b.png").unwrap();
+
+        let parts: Vec<_> = path.split('/').collect();
+        assert_eq!(parts[..2], ["", "image"]);
+        assert_eq!(parts[4], "https%3A%2F%2Fa%2Fb%2Epng");
+        assert!(signer.verify(parts[2], parts[3], "https://a/b.png"));
+    }
+}
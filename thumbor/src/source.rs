@@ -0,0 +1,281 @@
+use anyhow::{anyhow, bail, Result};
+use axum::http::StatusCode;
+use bytes::{Bytes, BytesMut};
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+use tracing::info;
+
+/// ImageSource fetches the original bytes of an image
+pub trait ImageSource: Send + Sync {
+    fn fetch<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Bytes>>;
+}
+
+/// FetchError tells why a source image could not be fetched. Sources return
+/// `TooLarge` and `Timeout` inside their `anyhow::Error`, anything else ends
+/// up as `Failed`
+#[derive(Debug, Clone)]
+pub enum FetchError {
+    /// the image is over `max_size`
+    TooLarge(String),
+    Timeout(String),
+    /// bad url, missing image or an error from the origin
+    Failed(String),
+}
+
+impl FetchError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            FetchError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            FetchError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            FetchError::Failed(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl From<&anyhow::Error> for FetchError {
+    fn from(e: &anyhow::Error) -> Self {
+        if let Some(e) = e.downcast_ref::<FetchError>() {
+            return e.clone();
+        }
+        match e.downcast_ref::<reqwest::Error>() {
+            Some(e) if e.is_timeout() => FetchError::Timeout(e.to_string()),
+            _ => FetchError::Failed(format!("{:#}", e)),
+        }
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::TooLarge(msg) => write!(f, "too large: {}", msg),
+            FetchError::Timeout(msg) => write!(f, "timed out: {}", msg),
+            FetchError::Failed(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+/// Limits applied to every fetch of a source
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub timeout: Duration,
+    pub max_size: usize,
+}
+
+impl Limits {
+    /// Read `<PREFIX>_TIMEOUT_SECS` and `<PREFIX>_MAX_SIZE`, falling back to the defaults
+    pub fn from_env(prefix: &str) -> Self {
+        let var = |name: &str| std::env::var(format!("{}_{}", prefix, name)).ok();
+        let default = Self::default();
+        Self {
+            timeout: var("TIMEOUT_SECS")
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(default.timeout),
+            max_size: var("MAX_SIZE")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.max_size),
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            max_size: 20 * 1024 * 1024,
+        }
+    }
+}
+
+/// Sources picks the image source by the scheme of the url
+#[derive(Clone, Default)]
+pub struct Sources {
+    sources: HashMap<String, Arc<dyn ImageSource>>,
+}
+
+impl Sources {
+    /// Register `source` for urls starting with `scheme://`
+    pub fn register(mut self, scheme: &str, source: impl ImageSource + 'static) -> Self {
+        self.sources.insert(scheme.to_string(), Arc::new(source));
+        self
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<Bytes> {
+        let (scheme, _) = url
+            .split_once("://")
+            .ok_or_else(|| anyhow!("url {} has no scheme", url))?;
+        let source = self
+            .sources
+            .get(&scheme.to_ascii_lowercase())
+            .ok_or_else(|| anyhow!("unsupported scheme {}", scheme))?;
+        source.fetch(url).await
+    }
+}
+
+/// HttpSource downloads images from http(s) origins
+#[derive(Clone)]
+pub struct HttpSource {
+    client: reqwest::Client,
+    limits: Limits,
+}
+
+impl HttpSource {
+    pub fn new(limits: Limits) -> Result<Self> {
+        let client = reqwest::Client::builder().timeout(limits.timeout).build()?;
+        Ok(Self { client, limits })
+    }
+}
+
+impl ImageSource for HttpSource {
+    fn fetch<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Bytes>> {
+        Box::pin(async move {
+            info!("send request to {}", url);
+            let mut resp = self.client.get(url).send().await?.error_for_status()?;
+            let max_size = self.limits.max_size;
+            if let Some(len) = resp.content_length() {
+                if len > max_size as u64 {
+                    bail!(FetchError::TooLarge(format!(
+                        "image is {} bytes, more than {}",
+                        len, max_size
+                    )));
+                }
+            }
+
+            // content-length may be missing or wrong, count what we receive
+            let mut data = BytesMut::new();
+            while let Some(chunk) = resp.chunk().await? {
+                if data.len() + chunk.len() > max_size {
+                    bail!(FetchError::TooLarge(format!(
+                        "image is more than {} bytes",
+                        max_size
+                    )));
+                }
+                data.extend_from_slice(&chunk);
+            }
+            Ok(data.freeze())
+        })
+    }
+}
+
+/// FileSource reads `file://` urls, relative to its root directory
+pub struct FileSource {
+    root: PathBuf,
+    limits: Limits,
+}
+
+impl FileSource {
+    pub fn new(root: impl Into<PathBuf>, limits: Limits) -> Result<Self> {
+        let root = root.into().canonicalize()?;
+        Ok(Self { root, limits })
+    }
+
+    async fn read(&self, url: &str) -> Result<Bytes> {
+        let path = url
+            .strip_prefix("file://")
+            .ok_or_else(|| anyhow!("{} is not a file url", url))?;
+        let path = self.root.join(path.trim_start_matches('/'));
+
+        // resolve symlinks and `..` before checking we are still under root
+        let path = tokio::fs::canonicalize(&path).await?;
+        if !path.starts_with(&self.root) {
+            bail!("{} is outside of {}", url, self.root.display());
+        }
+
+        let len = tokio::fs::metadata(&path).await?.len();
+        if len > self.limits.max_size as u64 {
+            bail!(FetchError::TooLarge(format!(
+                "image is {} bytes, more than {}",
+                len, self.limits.max_size
+            )));
+        }
+        Ok(tokio::fs::read(&path).await?.into())
+    }
+}
+
+impl ImageSource for FileSource {
+    fn fetch<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Bytes>> {
+        Box::pin(async move {
+            timeout(self.limits.timeout, self.read(url))
+                .await
+                .map_err(|_| FetchError::Timeout(format!("reading {} timed out", url)))?
+        })
+    }
+}
+
+/// MemorySource serves images registered up front, so tests need no network
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemorySource {
+    images: HashMap<String, Bytes>,
+}
+
+#[cfg(test)]
+impl MemorySource {
+    pub fn insert(mut self, url: &str, data: impl Into<Bytes>) -> Self {
+        self.images.insert(url.to_string(), data.into());
+        self
+    }
+}
+
+#[cfg(test)]
+impl ImageSource for MemorySource {
+    fn fetch<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Bytes>> {
+        Box::pin(async move {
+            self.images
+                .get(url)
+                .cloned()
+                .ok_or_else(|| anyhow!("{} not found", url))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_source(limits: Limits) -> FileSource {
+        FileSource::new(concat!(env!("CARGO_MANIFEST_DIR"), "/src"), limits).unwrap()
+    }
+
+    #[tokio::test]
+    async fn sources_should_dispatch_by_scheme() {
+        let sources =
+            Sources::default().register("mem", MemorySource::default().insert("mem://a", "a"));
+
+        assert_eq!(sources.fetch("mem://a").await.unwrap(), "a");
+        assert!(sources.fetch("mem://b").await.is_err());
+        assert!(sources.fetch("ftp://a").await.is_err());
+        assert!(sources.fetch("a").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn file_source_should_stay_under_root() {
+        let source = file_source(Limits::default());
+
+        assert!(source.fetch("file:///main.rs").await.is_ok());
+        assert!(source.fetch("file://main.rs").await.is_ok());
+        assert!(source.fetch("file:///../gopher.png").await.is_err());
+        assert!(source.fetch("file:///missing.png").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn file_source_should_enforce_max_size() {
+        let source = file_source(Limits {
+            max_size: 16,
+            ..Default::default()
+        });
+
+        let err = source.fetch("file:///main.rs").await.unwrap_err();
+        assert_eq!(
+            FetchError::from(&err).status(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+}